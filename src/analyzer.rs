@@ -0,0 +1,277 @@
+use crate::language::Language;
+use lazy_static::lazy_static;
+use std::env;
+use unicode_normalization::UnicodeNormalization;
+use unicode_normalization::char::is_combining_mark;
+use unicode_segmentation::UnicodeSegmentation;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TokenFilter {
+    Nfkc,
+    Lowercase,
+    FoldDiacritics,
+    // "1,000" and "1000" become the same term
+    NormalizeNumbers,
+    DropNumbers,
+    StopWords,
+    Stem,
+}
+
+pub struct Analyzer {
+    pub filters: Vec<TokenFilter>,
+    pub max_token_len: usize,
+    // runs of CJK characters are indexed as overlapping bigrams instead of single characters
+    pub cjk_bigrams: bool,
+}
+
+lazy_static! {
+    pub static ref ANALYZER: Analyzer = main::get_analyzer();
+}
+
+impl TokenFilter {
+    pub fn from_name(name: &str) -> Option<TokenFilter> {
+        match name.trim() {
+            "nfkc" => Some(TokenFilter::Nfkc),
+            "lowercase" => Some(TokenFilter::Lowercase),
+            "fold_diacritics" => Some(TokenFilter::FoldDiacritics),
+            "numbers" => Some(TokenFilter::NormalizeNumbers),
+            "drop_numbers" => Some(TokenFilter::DropNumbers),
+            "stopwords" => Some(TokenFilter::StopWords),
+            "stem" => Some(TokenFilter::Stem),
+            _ => Option::None,
+        }
+    }
+
+    // stop words and stemming change the word itself, the surface form is taken before them
+    pub fn is_linguistic(&self) -> bool {
+        *self == TokenFilter::StopWords || *self == TokenFilter::Stem
+    }
+
+    pub fn apply(&self, token: String, language: Language) -> Option<String> {
+        match self {
+            TokenFilter::Nfkc => Some(token.nfkc().collect()),
+            TokenFilter::Lowercase => Some(token.to_lowercase()),
+            TokenFilter::FoldDiacritics => {
+                Some(token.nfd().filter(|c| !is_combining_mark(*c)).nfc().collect())
+            }
+            TokenFilter::NormalizeNumbers => match is_number(&token) {
+                true => Some(token.replace([',', '_'], "")),
+                false => Some(token),
+            },
+            TokenFilter::DropNumbers => match is_number(&token) {
+                true => Option::None,
+                false => Some(token),
+            },
+            TokenFilter::StopWords => match language.is_stop_word(&token) {
+                true => Option::None,
+                false => Some(token),
+            },
+            TokenFilter::Stem => match language.get_stemmer() {
+                Some(stemmer) => Some(stemmer.stem(&token).to_string()),
+                None => Some(token),
+            },
+        }
+    }
+}
+
+fn is_number(token: &str) -> bool {
+    token.chars().any(|c| c.is_numeric())
+        && token.chars().all(|c| c.is_numeric() || c == ',' || c == '.' || c == '_')
+}
+
+// Han, Hiragana, Katakana and Hangul are written without spaces between words
+pub fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{1100}'..='\u{11FF}'
+        | '\u{3040}'..='\u{30FF}'
+        | '\u{3130}'..='\u{318F}'
+        | '\u{31F0}'..='\u{31FF}'
+        | '\u{3400}'..='\u{4DBF}'
+        | '\u{4E00}'..='\u{9FFF}'
+        | '\u{AC00}'..='\u{D7AF}'
+        | '\u{F900}'..='\u{FAFF}'
+        | '\u{20000}'..='\u{2A6DF}')
+}
+
+// with unigrams every character of the run is a token too, so a one character query, which
+// stays a unigram, still finds the runs it is part of
+fn get_bigrams(run: &str, with_unigrams: bool) -> Vec<String> {
+    let chars = run.chars().collect::<Vec<char>>();
+    if chars.len() == 1 {
+        return vec![run.to_string()];
+    }
+    let bigrams = chars.windows(2).map(|pair| pair.iter().collect());
+    match with_unigrams {
+        true => bigrams.chain(chars.iter().map(|c| c.to_string())).collect(),
+        false => bigrams.collect(),
+    }
+}
+
+impl Analyzer {
+    // returns (surface form, term) or None when a filter drops the token
+    pub fn filter_with(
+        &self,
+        token: &str,
+        language: Language,
+        filters: &[TokenFilter],
+    ) -> Option<(String, String)> {
+        let mut token = token.to_string();
+        let mut surface = Option::None;
+        for filter in filters.iter() {
+            if filter.is_linguistic() && surface.is_none() {
+                surface = Some(token.to_string());
+            }
+            token = filter.apply(token, language)?;
+        }
+        if token.is_empty() || token.chars().count() > self.max_token_len {
+            return Option::None;
+        }
+        Some((surface.unwrap_or(token.to_string()), token))
+    }
+
+    pub fn filter(&self, token: &str, language: Language) -> Option<String> {
+        self.filter_with(token, language, &self.filters)
+            .map(|(_, term)| term)
+    }
+
+    // UAX #29 word boundaries drop punctuation, so "rust," "(rust)" and "rust." all yield "rust".
+    // They split CJK text into single characters, adjacent ones are joined back and cut into bigrams
+    fn tokenize_with(&self, text: &str, cjk_unigrams: bool) -> Vec<String> {
+        let mut tokens = Vec::new();
+        let mut cjk_run = String::new();
+        let mut cjk_run_end = 0;
+        for (idx, word) in text.unicode_word_indices() {
+            if self.cjk_bigrams && word.chars().all(is_cjk) {
+                if idx != cjk_run_end && !cjk_run.is_empty() {
+                    tokens.append(&mut get_bigrams(&cjk_run, cjk_unigrams));
+                    cjk_run.clear();
+                }
+                cjk_run.push_str(word);
+                cjk_run_end = idx + word.len();
+                continue;
+            }
+            if !cjk_run.is_empty() {
+                tokens.append(&mut get_bigrams(&cjk_run, cjk_unigrams));
+                cjk_run.clear();
+            }
+            tokens.push(word.to_string());
+        }
+        if !cjk_run.is_empty() {
+            tokens.append(&mut get_bigrams(&cjk_run, cjk_unigrams));
+        }
+        tokens
+    }
+
+    // documents index CJK runs as bigrams and single characters
+    pub fn tokenize(&self, text: &str) -> Vec<String> {
+        self.tokenize_with(text, true)
+    }
+
+    // queries only as bigrams, a single character from a query matches the indexed unigram
+    pub fn tokenize_query(&self, text: &str) -> Vec<String> {
+        self.tokenize_with(text, false)
+    }
+
+    fn filter_tokens(&self, tokens: Vec<String>, language: Language) -> Vec<(String, String)> {
+        tokens
+            .into_iter()
+            .filter_map(|word| self.filter_with(&word, language, &self.filters))
+            .collect()
+    }
+
+    pub fn analyze(&self, text: &str, language: Language) -> Vec<(String, String)> {
+        self.filter_tokens(self.tokenize(text), language)
+    }
+
+    pub fn analyze_query(&self, text: &str, language: Language) -> Vec<(String, String)> {
+        self.filter_tokens(self.tokenize_query(text), language)
+    }
+
+    // same filters without tokenizing, for patterns like `rü*` that must keep their wildcards
+    pub fn normalize(&self, text: &str) -> String {
+        let mut text = text.to_string();
+        for filter in self.filters.iter() {
+            if filter.is_linguistic()
+                || *filter == TokenFilter::DropNumbers
+                || *filter == TokenFilter::NormalizeNumbers
+            {
+                continue;
+            }
+            text = filter.apply(text, Language::Unknown).unwrap_or_default();
+        }
+        text
+    }
+}
+
+pub mod main {
+    use super::*;
+
+    pub fn get_analyzer() -> Analyzer {
+        let filters = env::var("ANALYZER_FILTERS")
+            .unwrap_or(String::from(
+                "nfkc,lowercase,stopwords,stem,fold_diacritics,numbers",
+            ))
+            .split(',')
+            .filter_map(TokenFilter::from_name)
+            .collect();
+        let max_token_len = env::var("ANALYZER_MAX_TOKEN_LEN")
+            .unwrap_or(String::from("64"))
+            .parse::<usize>()
+            .unwrap();
+        let cjk_bigrams = env::var("ANALYZER_CJK_MODE").unwrap_or(String::from("bigram")) == "bigram";
+        Analyzer {
+            filters,
+            max_token_len,
+            cjk_bigrams,
+        }
+    }
+
+    pub fn analyze_query(text: &str, language: Language) -> Vec<String> {
+        ANALYZER
+            .analyze(text, language)
+            .into_iter()
+            .map(|(_, term)| term)
+            .collect()
+    }
+
+    pub fn normalize(text: &str) -> String {
+        ANALYZER.normalize(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_terms(text: &str, language: Language) -> Vec<String> {
+        ANALYZER.analyze_query(text, language).into_iter().map(|(_, term)| term).collect()
+    }
+
+    #[test]
+    fn nfkc_and_lowercase_unify_compatibility_forms() {
+        // fullwidth letters and digits and a ligature
+        assert_eq!(get_terms("ＲＵＳＴ ﬁle １２３", Language::Unknown), vec!["rust", "file", "123"]);
+        assert_eq!(get_terms("1,000 1000", Language::Unknown), vec!["1000", "1000"]);
+    }
+
+    #[test]
+    fn diacritics_are_folded_after_stemming_and_kept_in_the_surface_form() {
+        assert_eq!(get_terms("Crème BRÛLÉE naïve", Language::Unknown), vec!["creme", "brulee", "naive"]);
+        assert_eq!(
+            ANALYZER.analyze("Crème", Language::Unknown),
+            vec![(String::from("crème"), String::from("creme"))]
+        );
+        assert_eq!(main::normalize("Rü*"), "ru*");
+    }
+
+    #[test]
+    fn cjk_runs_become_overlapping_bigrams() {
+        assert_eq!(ANALYZER.tokenize_query("東京大学"), vec!["東京", "京大", "大学"]);
+        assert_eq!(ANALYZER.tokenize_query("rust 東京 和 code"), vec!["rust", "東京", "和", "code"]);
+        assert_eq!(ANALYZER.tokenize_query("東京、大阪"), vec!["東京", "大阪"]);
+        assert_eq!(ANALYZER.tokenize_query("猫"), vec!["猫"]);
+        // documents also get every character, the unigram a one character query looks up
+        assert_eq!(ANALYZER.tokenize("黒猫 x"), vec!["黒猫", "黒", "猫", "x"]);
+        assert_eq!(ANALYZER.tokenize("猫"), vec!["猫"]);
+    }
+}
@@ -1,5 +1,7 @@
+use crate::language;
+use crate::recency;
 use crate::url_index;
-use chrono;
+use chrono::{DateTime, Utc};
 use lazy_static::lazy_static;
 use reqwest::Client;
 use scraper::Html;
@@ -14,9 +16,17 @@ use std::{cmp, thread, time::Duration};
 #[derive(Debug)]
 struct UrlResp {
     urls: Vec<String>,
+    #[allow(dead_code)]
     is_fetched: bool,
 }
 
+struct FetchResp {
+    body: String,
+    content_language: Option<String>,
+    last_modified: Option<String>,
+    content_type: Option<String>,
+}
+
 struct QueueEle {
     urls: Vec<String>,
     depth: u8,
@@ -31,13 +41,15 @@ pub mod main {
     use super::*;
     fn get_seed_file() -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
         let filepath = &env::var("SEED_URLS_FILE_PATH")?;
-        let file_data = fs::OpenOptions::new()
+        // creates a missing seed file and leaves an existing one as it is
+        fs::OpenOptions::new()
             .create(true)
             .write(true)
+            .truncate(false)
             .open(filepath)?;
         let file_data = fs::read_to_string(filepath)?;
         let seed_urls = file_data.lines().map(String::from).collect();
-        return Ok(seed_urls);
+        Ok(seed_urls)
     }
 
     fn save_fetch_log(url: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
@@ -51,21 +63,94 @@ pub mod main {
         Ok(())
     }
 
-    async fn fetch_data(url: &str) -> Result<String, Box<dyn Error + Sync + Send>> {
+    async fn fetch_data(url: &str) -> Result<FetchResp, Box<dyn Error + Sync + Send>> {
         println!("started fetching url : {url}");
-        let data = client
+        let resp = client
             .get(url)
             .timeout(Duration::from_secs(10))
             .header("accept", "text/html")
             .header("user-agent", "crawler")
             .send()
-            .await?
-            .text()
             .await?;
+        let get_header = |name: &str| {
+            resp.headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(String::from)
+        };
+        let content_language = get_header("content-language");
+        let last_modified = get_header("last-modified");
+        let content_type = get_header("content-type");
+        let body = resp.text().await?;
         let _ = save_fetch_log(url);
-        Ok(data)
+        Ok(FetchResp {
+            body,
+            content_language,
+            last_modified,
+            content_type,
+        })
+    }
+
+    pub fn get_html_lang(document: &Html) -> Option<String> {
+        document
+            .select(&Selector::parse("html").unwrap())
+            .next()
+            .and_then(|element| element.value().attr("lang"))
+            .map(String::from)
+    }
+
+    // schema.org dates can sit on the top object, inside @graph or in a list of objects
+    fn get_json_ld_date(value: &serde_json::Value, key: &str) -> Option<String> {
+        match value {
+            serde_json::Value::Object(object) => {
+                if let Some(date) = object.get(key).and_then(|date| date.as_str()) {
+                    return Some(date.to_string());
+                }
+                object.values().find_map(|value| get_json_ld_date(value, key))
+            }
+            serde_json::Value::Array(values) => values.iter().find_map(|value| get_json_ld_date(value, key)),
+            _ => Option::None,
+        }
+    }
+
+    // publication date first, then the last modification, then the Last-Modified header
+    fn get_published_date(document: &Html, last_modified: Option<&str>) -> Option<DateTime<Utc>> {
+        let get_meta_date = |selector: &str| {
+            document
+                .select(&Selector::parse(selector).unwrap())
+                .filter_map(|element| {
+                    let value = element.value();
+                    value.attr("content").or(value.attr("datetime"))
+                })
+                .find_map(recency::main::parse_date)
+        };
+        let json_ld = document
+            .select(&Selector::parse("script[type='application/ld+json']").unwrap())
+            .filter_map(|element| serde_json::from_str::<serde_json::Value>(&element.text().collect::<String>()).ok())
+            .collect::<Vec<serde_json::Value>>();
+        let get_json_ld = |key: &str| {
+            json_ld
+                .iter()
+                .filter_map(|value| get_json_ld_date(value, key))
+                .find_map(|date| recency::main::parse_date(&date))
+        };
+        get_meta_date(
+            "meta[property='article:published_time'], meta[name='pubdate'], meta[name='publishdate'], \
+             meta[name='date'], meta[name='dc.date'], meta[name='DC.date.issued'], meta[itemprop='datePublished']",
+        )
+        .or_else(|| get_json_ld("datePublished"))
+        .or_else(|| {
+            get_meta_date(
+                "meta[property='article:modified_time'], meta[property='og:updated_time'], \
+                 meta[name='last-modified'], meta[itemprop='dateModified']",
+            )
+        })
+        .or_else(|| get_json_ld("dateModified"))
+        .or_else(|| get_meta_date("time[datetime]"))
+        .or_else(|| last_modified.and_then(recency::main::parse_date))
     }
 
+    #[allow(dead_code)]
     fn get_meta_description(document: &Html) -> Result<String, Box<dyn Error + Send + Sync>> {
         let meta_description = document
             .select(&Selector::parse("meta[name='description']").unwrap())
@@ -127,12 +212,17 @@ pub mod main {
                 is_fetched: false,
             });
         }
-        let data = fetch_data(&url).await?;
-        let document = scraper::Html::parse_document(&data);
+        let FetchResp {
+            body,
+            content_language,
+            last_modified,
+            content_type,
+        } = fetch_data(url).await?;
+        let document = scraper::Html::parse_document(&body);
         let urls = get_urls(&document)?;
         let url_node = url_index::main::get_by_url(url);
-        if url_node.is_some() && force_fetch == false {
-            let url_timestamp = url_node.as_ref().unwrap().timestamp;
+        if let (Some(node), false) = (&url_node, force_fetch) {
+            let url_timestamp = node.timestamp;
             let curr_timestamp = chrono::Utc::now();
             let date_diff_days = (curr_timestamp - url_timestamp).num_days();
             let req_date_diff = &env::var("CRAWL_DATE_DIFF_FOR_UPDATE")
@@ -141,7 +231,7 @@ pub mod main {
                 .unwrap();
             if date_diff_days < *req_date_diff {
                 return Ok(UrlResp {
-                    urls: urls,
+                    urls,
                     is_fetched: true,
                 });
             }
@@ -157,28 +247,46 @@ pub mod main {
             "div, article, main, section, p, [class*='content'], [class*='post'], [class*='story']",
         )?;
         let mut index_content = true;
-        match url_node {
-            Some(node) => {
-                let content_hash = node.hash;
-                let curr_hash = url_index::main::get_hash(&content);
-                if curr_hash == content_hash {
-                    index_content = false;
-                }
+        if let Some(node) = &url_node {
+            let curr_hash = url_index::main::get_hash(&content);
+            if curr_hash == node.hash {
+                index_content = false;
             }
-            None => (),
         }
         if index_content {
-            url_index::main::insert(url, &content, &title, &headings, &highlighted);
+            let language = language::main::detect_document(
+                get_html_lang(&document).as_deref(),
+                content_language.as_deref(),
+                &content,
+            );
+            let published = get_published_date(&document, last_modified.as_deref());
+            // `text/html; charset=utf-8` is stored as `text/html`
+            let mime_type = content_type
+                .as_deref()
+                .and_then(|value| value.split(';').next())
+                .map(|value| value.trim().to_lowercase())
+                .unwrap_or_default();
+            url_index::main::insert(
+                url,
+                &content,
+                &title,
+                &headings,
+                &highlighted,
+                language,
+                published,
+                &mime_type,
+            )?;
             crate::inverted_index::main::insert_by_content(
                 url,
                 &content,
                 &title,
                 &headings,
                 &highlighted,
+                language,
             );
         }
         Ok(UrlResp {
-            urls: urls,
+            urls,
             is_fetched: true,
         })
     }
@@ -190,7 +298,7 @@ pub mod main {
     ) -> Result<(), Box<dyn Error + Send + Sync>> {
         let mut dqueue: VecDeque<QueueEle> = VecDeque::from([QueueEle { urls, depth }]);
         let mut visited: HashSet<String> = HashSet::new();
-        while dqueue.len() != 0 {
+        while !dqueue.is_empty() {
             let QueueEle {
                 urls: curr_urls,
                 depth: curr_depth,
@@ -223,13 +331,11 @@ pub mod main {
                     urls,
                     is_fetched: _,
                 } = handled_resp.unwrap();
-                let mut idx = 0;
-                for url in urls {
+                for (idx, url) in urls.into_iter().enumerate() {
                     while next_results.len() <= idx {
                         next_results.push(Vec::new());
                     }
                     next_results[idx].push(url);
-                    idx += 1;
                 }
             }
             for urls in next_results {
@@ -268,7 +374,7 @@ pub mod main {
         }
         let seed_urls = seed_urls.unwrap_or(vec![]);
         let mut splitted_seed_urls: Vec<Vec<String>> =
-            (0..available_threads).map(|el| Vec::new()).collect();
+            (0..available_threads).map(|_el| Vec::new()).collect();
         for (idx, url) in seed_urls.iter().enumerate() {
             splitted_seed_urls[idx % available_threads as usize].push(url.to_string());
         }
@@ -295,7 +401,7 @@ pub mod main {
             threads.push(handle);
         }
         for handle in threads {
-            let _ = handle.join().unwrap();
+            handle.join().unwrap();
         }
         Ok(())
     }
@@ -311,3 +417,16 @@ pub mod main {
         println!("url processed resp url: {url}");
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn html_lang_is_read_with_its_region_subtag() {
+        let document = Html::parse_document("<html lang=\"en-US\"><body><p>hello</p></body></html>");
+        assert_eq!(main::get_html_lang(&document), Some(String::from("en-US")));
+        let document = Html::parse_document("<html><body><p>hello</p></body></html>");
+        assert_eq!(main::get_html_lang(&document), Option::None);
+    }
+}
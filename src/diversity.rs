@@ -0,0 +1,131 @@
+use crate::inverted_index::{self, Field, ResultScore};
+use crate::url_index;
+use std::collections::HashMap;
+use std::env;
+
+pub mod main {
+    use super::*;
+
+    // results of one host kept before the rest are collapsed into a "more from this site" count
+    pub fn get_max_per_host() -> usize {
+        env::var("SEARCH_MAX_PER_HOST")
+            .unwrap_or(String::from("2"))
+            .parse::<usize>()
+            .unwrap()
+    }
+
+    // 1 ranks by relevance only, 0 by novelty only
+    fn get_mmr_lambda() -> f64 {
+        env::var("MMR_LAMBDA")
+            .unwrap_or(String::from("0.7"))
+            .parse::<f64>()
+            .unwrap()
+    }
+
+    // how many top scored documents per returned result are reordered
+    pub fn get_mmr_candidates(k: usize) -> usize {
+        let factor = env::var("MMR_CANDIDATES")
+            .unwrap_or(String::from("3"))
+            .parse::<usize>()
+            .unwrap();
+        k.saturating_mul(factor.max(1))
+    }
+
+    fn get_doc_vector(url: &str) -> HashMap<String, u64> {
+        url_index::main::get_with(url, |data_node| {
+            let mut vector =
+                inverted_index::main::get_term_counts(Field::Content, &data_node.content, data_node.language);
+            let title = inverted_index::main::get_term_counts(Field::Title, &data_node.title, data_node.language);
+            for (term, count) in title {
+                *vector.entry(term).or_insert(0) += count;
+            }
+            vector
+        })
+        .unwrap_or_default()
+    }
+
+    pub fn get_cosine(a: &HashMap<String, u64>, b: &HashMap<String, u64>) -> f64 {
+        let norm = |vector: &HashMap<String, u64>| vector.values().map(|count| (count * count) as f64).sum::<f64>().sqrt();
+        let (norm_a, norm_b) = (norm(a), norm(b));
+        if norm_a == 0.0 || norm_b == 0.0 {
+            return 0.0;
+        }
+        let dot = a
+            .iter()
+            .filter_map(|(term, count)| b.get(term).map(|other| (count * other) as f64))
+            .sum::<f64>();
+        dot / (norm_a * norm_b)
+    }
+
+    // maximal marginal relevance: each pick maximizes lambda * relevance - (1 - lambda) * the
+    // highest similarity to an earlier pick. relevance is the score over the best one, returns
+    // the picked indexes in order
+    pub fn get_mmr_order(
+        scores: &[f64],
+        similarity: impl Fn(usize, usize) -> f64,
+        lambda: f64,
+        k: usize,
+    ) -> Vec<usize> {
+        let max_score = scores.iter().cloned().fold(0.0, f64::max);
+        let mut max_similarity = vec![0.0; scores.len()];
+        let mut remaining = (0..scores.len()).collect::<Vec<usize>>();
+        let mut order = Vec::new();
+        while order.len() < k && !remaining.is_empty() {
+            let mmr = |idx: usize| {
+                let relevance = if max_score > 0.0 { scores[idx] / max_score } else { 0.0 };
+                lambda * relevance - (1.0 - lambda) * max_similarity[idx]
+            };
+            // first of the ties wins, remaining keeps the score order
+            let mut best = 0;
+            for pos in 1..remaining.len() {
+                if mmr(remaining[pos]) > mmr(remaining[best]) {
+                    best = pos;
+                }
+            }
+            let picked = remaining.remove(best);
+            for idx in remaining.iter() {
+                max_similarity[*idx] = f64::max(max_similarity[*idx], similarity(picked, *idx));
+            }
+            order.push(picked);
+        }
+        order
+    }
+
+    // pages of one host count as duplicates of each other, others are compared by their terms
+    pub fn diversify(results: Vec<ResultScore>, k: usize) -> Vec<ResultScore> {
+        let hosts = results
+            .iter()
+            .map(|result| url_index::main::get_host(&result.url))
+            .collect::<Vec<String>>();
+        let vectors = results
+            .iter()
+            .map(|result| get_doc_vector(&result.url))
+            .collect::<Vec<HashMap<String, u64>>>();
+        let scores = results.iter().map(|result| result.score).collect::<Vec<f64>>();
+        let similarity = |a: usize, b: usize| match hosts[a] == hosts[b] {
+            true => 1.0,
+            false => get_cosine(&vectors[a], &vectors[b]),
+        };
+        let order = get_mmr_order(&scores, similarity, get_mmr_lambda(), k);
+        let mut results = results.into_iter().map(Some).collect::<Vec<Option<ResultScore>>>();
+        order.into_iter().filter_map(|idx| results[idx].take()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mmr_moves_near_duplicates_down() {
+        // 0 and 1 are the same page, 2 scores a bit lower but is different
+        let scores = [1.0, 0.95, 0.8, 0.3];
+        let similarity = |a: usize, b: usize| match (a.min(b), a.max(b)) {
+            (0, 1) => 0.98,
+            _ => 0.1,
+        };
+        assert_eq!(main::get_mmr_order(&scores, similarity, 1.0, 4), vec![0, 1, 2, 3]);
+        assert_eq!(main::get_mmr_order(&scores, similarity, 0.7, 4), vec![0, 2, 1, 3]);
+        assert_eq!(main::get_mmr_order(&scores, similarity, 0.7, 2), vec![0, 2]);
+    }
+}
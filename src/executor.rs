@@ -0,0 +1,661 @@
+use crate::inverted_index::{
+    self, Bm25Config, Explanation, Field, FieldStats, ResultScore, SearchHits, TermExplanation, BM25_CONFIG,
+};
+use crate::diversity;
+use crate::facets::{self, FacetCounter};
+use crate::query::{ParsedQuery, QueryTerm, SearchOptions, SortOrder};
+use crate::recency::DECAY_CONFIG;
+use crate::postings::PostingList;
+use crate::segment::{self, SegmentReader, Snapshot, TermPostings};
+use crate::stats::{self, CorpusStats};
+use crate::url_index::{self, DocMeta};
+use float_ord::FloatOrd;
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+// one expanded query term, its posting list in every segment and the most it can add to
+// any document's score
+pub struct TermCursor<'a> {
+    pub term: QueryTerm,
+    // the term's postings in a segment and its list for the term's field
+    pub lists: Vec<(&'a SegmentReader, &'a TermPostings, &'a PostingList)>,
+    // documents of the `site:` filter, the others are skipped
+    pub site_docs: Option<&'a HashSet<u32>>,
+    pub max_score: f64,
+}
+
+impl TermCursor<'_> {
+    // skip data takes a lookup straight to the one block that can hold the document
+    pub fn contains(&self, doc_id: u32) -> bool {
+        self.site_docs.is_none_or(|site_docs| site_docs.contains(&doc_id))
+            && self
+                .lists
+                .iter()
+                .any(|(reader, _, docs)| reader.is_live(doc_id) && docs.contains(doc_id))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = u32> + '_ {
+        self.lists
+            .iter()
+            .flat_map(|(reader, _, docs)| docs.iter().filter(|doc_id| reader.is_live(*doc_id)))
+            .filter(|doc_id| self.site_docs.is_none_or(|site_docs| site_docs.contains(doc_id)))
+    }
+
+    // the postings of the one segment the document is live in
+    pub fn get_postings(&self, doc_id: u32) -> Option<&TermPostings> {
+        self.lists
+            .iter()
+            .find(|(reader, _, docs)| reader.is_live(doc_id) && docs.contains(doc_id))
+            .map(|(_, postings, _)| *postings)
+    }
+}
+
+// (sort key, score, url)
+type TopKEntry = (FloatOrd<f64>, FloatOrd<f64>, String);
+
+// the k best documents seen so far by sort key, the weakest of them comes first. with a
+// per-host limit a host's weakest document makes way for a better one from the same host
+pub struct TopK {
+    k: usize,
+    per_host: usize,
+    entries: BTreeSet<TopKEntry>,
+    host_counts: HashMap<String, usize>,
+}
+
+impl TopK {
+    pub fn new(k: usize, per_host: usize) -> TopK {
+        TopK {
+            k,
+            per_host,
+            entries: BTreeSet::new(),
+            host_counts: HashMap::new(),
+        }
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.entries.len() >= self.k
+    }
+
+    // key a document has to beat to get in, only meaningful once the heap is full
+    pub fn get_threshold(&self) -> f64 {
+        self.entries.first().map_or(0.0, |(key, _, _)| key.0)
+    }
+
+    pub fn push(&mut self, url: &str, score: f64, key: f64) {
+        if self.k == 0 {
+            return;
+        }
+        let entry = (FloatOrd(key), FloatOrd(score), url.to_string());
+        if self.per_host > 0 {
+            let host = url_index::main::get_host(url);
+            let count = self.host_counts.entry(host.to_string()).or_insert(0);
+            if *count >= self.per_host {
+                let weakest = self
+                    .entries
+                    .iter()
+                    .find(|(_, _, url)| url_index::main::get_host(url) == host)
+                    .cloned();
+                match weakest {
+                    Some(weakest) if weakest < entry => self.entries.remove(&weakest),
+                    _ => return,
+                };
+            } else {
+                *count += 1;
+            }
+        }
+        self.entries.insert(entry);
+        if self.entries.len() > self.k
+            && let Some((_, _, url)) = self.entries.pop_first()
+        {
+            self.remove_host(&url);
+        }
+    }
+
+    fn remove_host(&mut self, url: &str) {
+        if self.per_host == 0 {
+            return;
+        }
+        let host = url_index::main::get_host(url);
+        if let Some(count) = self.host_counts.get_mut(&host) {
+            *count -= 1;
+            if *count == 0 {
+                self.host_counts.remove(&host);
+            }
+        }
+    }
+
+    // titles are only read for the documents that made it
+    pub fn into_results(self) -> Vec<ResultScore> {
+        let mut results = self.entries.into_iter().collect::<Vec<TopKEntry>>();
+        results.sort_by(|a, b| b.0.cmp(&a.0).then(b.1.cmp(&a.1)).then(a.2.cmp(&b.2)));
+        results
+            .into_iter()
+            .map(|(_, score, url)| ResultScore {
+                title: url_index::main::get_with(&url, |node| node.title.to_string()).unwrap_or_default(),
+                url,
+                score: score.0,
+                snippets: vec![],
+                more_from_host: 0,
+                explanation: Option::None,
+            })
+            .collect()
+    }
+}
+
+// the document filters of a query, with filter values resolved to interned ids once
+pub struct DocFilter<'a> {
+    pub options: &'a SearchOptions,
+    mime_types: Option<HashSet<u32>>,
+}
+
+impl DocFilter<'_> {
+    pub fn new(options: &SearchOptions) -> DocFilter<'_> {
+        let mime_types = match options.mime_types.is_empty() {
+            true => Option::None,
+            false => {
+                let meta_columns = url_index::META_COLUMNS.read().unwrap();
+                let ids = options
+                    .mime_types
+                    .iter()
+                    .filter_map(|mime_type| meta_columns.get_id(&mime_type.trim().to_lowercase()))
+                    .collect();
+                Some(ids)
+            }
+        };
+        DocFilter { options, mime_types }
+    }
+
+    pub fn is_match(&self, meta: &DocMeta) -> bool {
+        let options = self.options;
+        options.language.is_none_or(|filter| filter == meta.language)
+            && options.after.is_none_or(|after| meta.get_date() >= after)
+            && options.before.is_none_or(|before| meta.get_date() <= before)
+            && self.mime_types.as_ref().is_none_or(|ids| ids.contains(&meta.mime_type))
+    }
+}
+
+pub mod main {
+    use super::*;
+
+    // the configured boosts with the ones passed for this query on top
+    fn get_bm25_config(options: &SearchOptions) -> Bm25Config {
+        let mut config = BM25_CONFIG.clone();
+        for (field, boost) in options.field_boosts.iter() {
+            if let Some(params) = config.fields.get_mut(field) {
+                params.boost = *boost;
+            }
+        }
+        config
+    }
+
+    fn get_sort_key(options: &SearchOptions, meta: &DocMeta, score: f64) -> f64 {
+        match options.sort {
+            SortOrder::Relevance => score,
+            SortOrder::Date => meta.get_date().timestamp_millis() as f64,
+        }
+    }
+
+    fn get_cursors<'a>(
+        snapshot: &'a Snapshot,
+        terms: Vec<QueryTerm>,
+        site_docs: Option<&'a HashSet<u32>>,
+        corpus_stats: &CorpusStats,
+        boost: f64,
+    ) -> Vec<TermCursor<'a>> {
+        let mut cursors = Vec::new();
+        for term in terms {
+            let lists = snapshot.get_lists(&term.text, term.field);
+            let doc_freq = corpus_stats.get_doc_freq(&term.text, term.field);
+            let max_score = inverted_index::main::get_max_bm25f_score(
+                &BM25_CONFIG,
+                corpus_stats.total_count,
+                doc_freq,
+            ) * term.weight
+                * boost;
+            let cursor = TermCursor {
+                term,
+                lists,
+                site_docs,
+                max_score,
+            };
+            if cursor.iter().next().is_some() {
+                cursors.push(cursor);
+            }
+        }
+        // highest impact first, the tail of the list is what MaxScore gets to skip
+        cursors.sort_by(|a, b| b.max_score.total_cmp(&a.max_score));
+        cursors
+    }
+
+    // scores are summed per document and multiplied by the number of query words matched
+    fn get_group_bound<'a>(cursors: impl Iterator<Item = &'a TermCursor<'a>>) -> f64 {
+        let mut groups = HashSet::new();
+        let mut bound = 0.0;
+        for cursor in cursors {
+            groups.insert(cursor.term.group);
+            bound += cursor.max_score;
+        }
+        bound * groups.len().max(1) as f64
+    }
+
+    // cursors are the ones whose postings have the document, term frequencies come from
+    // them and field lengths from the meta columns, the document text isn't read
+    fn get_doc_score(
+        doc_id: u32,
+        meta: &DocMeta,
+        cursors: &[&TermCursor],
+        corpus_stats: &CorpusStats,
+        config: &Bm25Config,
+        boost: f64,
+        recency_boost: f64,
+    ) -> Explanation {
+        let mut term_score = 0.0;
+        let mut groups = HashSet::new();
+        let mut terms = Vec::new();
+        for cursor in cursors.iter() {
+            let term = &cursor.term;
+            // a field-scoped term (`title:rust`) is only scored against that field
+            let fields = match term.field {
+                Some(field) => vec![field],
+                None => Field::SCORED.to_vec(),
+            };
+            let postings = cursor.get_postings(doc_id);
+            let mut field_stats = Vec::<FieldStats>::new();
+            for field in fields {
+                let term_freq = postings
+                    .and_then(|postings| postings.get_docs(Some(field)))
+                    .map_or(0, |docs| docs.get_freq(doc_id) as u64);
+                field_stats.push(inverted_index::main::get_field_stats(
+                    field,
+                    term_freq,
+                    &meta.field_lengths,
+                    corpus_stats,
+                ));
+            }
+            let bm25f = inverted_index::main::get_bm25f_explanation(
+                config,
+                &field_stats,
+                corpus_stats.total_count,
+                corpus_stats.get_doc_freq(&term.text, term.field),
+            );
+            let score = bm25f.score * term.weight * boost;
+            term_score += score;
+            groups.insert(term.group);
+            terms.push(TermExplanation {
+                term: term.text.to_string(),
+                field: term.field,
+                group: term.group,
+                weight: term.weight,
+                language_boost: boost,
+                score,
+                bm25f,
+            });
+        }
+        Explanation {
+            // boosting score for pages which has entire search text
+            score: term_score * groups.len().max(1) as f64 * recency_boost,
+            term_score,
+            matched_groups: groups.len(),
+            recency_boost,
+            terms,
+        }
+    }
+
+    // matching documents counted by facet value, over the postings without scoring so it
+    // stays exact when MaxScore skips documents. the interned values are read by doc id under
+    // one lock, no url is resolved
+    fn get_facet_counter(cursors: &[TermCursor], filter: &DocFilter) -> FacetCounter {
+        let mut counter = FacetCounter::new(filter.options.date_interval);
+        let mut seen_docs = HashSet::new();
+        let meta_columns = url_index::META_COLUMNS.read().unwrap();
+        for doc_id in cursors.iter().flat_map(|cursor| cursor.iter()) {
+            if !seen_docs.insert(doc_id) {
+                continue;
+            }
+            if let Some(meta) = meta_columns.get_by_doc_id(doc_id).filter(|meta| filter.is_match(meta)) {
+                counter.add(&meta);
+            }
+        }
+        counter
+    }
+
+    // hits left after collapsing, what a client can page through
+    fn get_total_hits(host_hits: &HashMap<u32, usize>, per_host: usize) -> usize {
+        host_hits
+            .values()
+            .map(|count| match per_host {
+                0 => *count,
+                _ => (*count).min(per_host),
+            })
+            .sum()
+    }
+
+    // a bare `site:` query lists every page of that site
+    fn get_site_results(
+        site_docs: &HashSet<u32>,
+        filter: &DocFilter,
+        top_k: &mut TopK,
+        counter: &mut FacetCounter,
+    ) {
+        for doc_id in site_docs.iter() {
+            let Some(meta) = url_index::main::get_meta_by_doc_id(*doc_id).filter(|meta| filter.is_match(meta)) else {
+                continue;
+            };
+            let Some(url) = segment::main::get_url(*doc_id) else {
+                continue;
+            };
+            counter.add(&meta);
+            top_k.push(&url, 0.0, get_sort_key(filter.options, &meta, 0.0));
+        }
+    }
+
+    // document-at-a-time scoring on the calling thread. lists are walked from the highest
+    // impact term down and a document is only fetched and scored when its upper bound can
+    // still beat the current k-th score (MaxScore), once the remaining lists together can't
+    // beat it the walk stops early
+    pub fn execute(query: &ParsedQuery, options: &SearchOptions, k: usize) -> SearchHits {
+        // `site:` asks for one site, collapsing it would hide what was asked for
+        let per_host = match query.sites.is_empty() {
+            true => options.per_host,
+            false => 0,
+        };
+        // mmr reorders by score, it has nothing to work with under another sort
+        let diversify = options.diversify && options.sort == SortOrder::Relevance && !query.terms.is_empty();
+        let pool = match diversify {
+            true => diversity::main::get_mmr_candidates(k),
+            false => k,
+        };
+        let mut top_k = TopK::new(pool, per_host);
+        let filter = DocFilter::new(options);
+        // every posting list of the query comes from one snapshot
+        let snapshot = segment::main::get_snapshot();
+        let site_docs = inverted_index::main::get_site_docs(&snapshot, &query.sites);
+        if query.terms.is_empty() {
+            let mut counter = FacetCounter::new(options.date_interval);
+            if let Some(site_docs) = &site_docs {
+                get_site_results(site_docs, &filter, &mut top_k, &mut counter);
+            }
+            return SearchHits {
+                results: top_k.into_results(),
+                total_hits: counter.hosts.values().sum(),
+                terms: HashSet::new(),
+                facets: facets::main::get_facets(&counter),
+            };
+        }
+        let language_boost = inverted_index::main::get_language_boost();
+        let terms = inverted_index::main::expand_query_terms(&query.terms, options.fuzzy);
+        let term_texts = terms.iter().map(|term| term.text.as_str()).collect::<Vec<&str>>();
+        // a snapshot, the stats lock must not be held while the url index is read
+        let corpus_stats = stats::main::get_snapshot(&term_texts);
+        // upper bounds assume the preferred language boost applies, scores apply it per document
+        let max_boost = match options.preferred_language {
+            Some(_) => language_boost.max(1.0),
+            None => 1.0,
+        };
+        let max_boost = match options.recency {
+            true => max_boost * DECAY_CONFIG.get_max_boost(),
+            false => max_boost,
+        };
+        let now = chrono::Utc::now();
+        let highlight_terms = terms.iter().map(|term| term.text.to_string()).collect::<HashSet<String>>();
+        let cursors = get_cursors(&snapshot, terms, site_docs.as_ref(), &corpus_stats, max_boost);
+        let counter = get_facet_counter(&cursors, &filter);
+        let total_hits = get_total_hits(&counter.hosts, per_host);
+        let config = get_bm25_config(options);
+        // bounds are on the score, they can only prune when results are ranked by it
+        let can_prune = options.sort == SortOrder::Relevance;
+        let mut seen_docs = HashSet::new();
+        let mut explanations = HashMap::new();
+        for idx in 0..cursors.len() {
+            let remaining = &cursors[idx..];
+            if can_prune && top_k.is_full() && get_group_bound(remaining.iter()) <= top_k.get_threshold() {
+                break;
+            }
+            for doc_id in cursors[idx].iter() {
+                if !seen_docs.insert(doc_id) {
+                    continue;
+                }
+                // documents first seen in this list can't be in any of the earlier ones
+                let matched = remaining
+                    .iter()
+                    .filter(|cursor| cursor.contains(doc_id))
+                    .collect::<Vec<&TermCursor>>();
+                if can_prune && top_k.is_full() && get_group_bound(matched.iter().copied()) <= top_k.get_threshold() {
+                    continue;
+                }
+                let Some(meta) = url_index::main::get_meta_by_doc_id(doc_id).filter(|meta| filter.is_match(meta)) else {
+                    continue;
+                };
+                let Some(url) = segment::main::get_url(doc_id) else {
+                    continue;
+                };
+                let boost = match options.preferred_language == Some(meta.language) {
+                    true => language_boost,
+                    false => 1.0,
+                };
+                let recency_boost = match options.recency {
+                    true => DECAY_CONFIG.get_boost(meta.published, now),
+                    false => 1.0,
+                };
+                let explanation =
+                    get_doc_score(doc_id, &meta, &matched, &corpus_stats, &config, boost, recency_boost);
+                let score = explanation.score;
+                top_k.push(&url, score, get_sort_key(options, &meta, score));
+                if options.explain {
+                    explanations.insert(url, explanation);
+                }
+            }
+        }
+        let results = match diversify {
+            true => diversity::main::diversify(top_k.into_results(), k),
+            false => top_k.into_results(),
+        };
+        let results = results
+            .into_iter()
+            .map(|result| {
+                let host_count = url_index::main::get_meta(&result.url)
+                    .and_then(|meta| counter.hosts.get(&meta.host).copied());
+                ResultScore {
+                    explanation: explanations.remove(&result.url),
+                    more_from_host: match per_host {
+                        0 => 0,
+                        _ => host_count.unwrap_or(0).saturating_sub(per_host),
+                    },
+                    ..result
+                }
+            })
+            .collect();
+        SearchHits {
+            results,
+            total_hits,
+            terms: highlight_terms,
+            facets: facets::main::get_facets(&counter),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::language::Language;
+    use crate::query;
+
+    #[test]
+    fn max_score_top_k_matches_exhaustive_scoring() {
+        let _lock = url_index::TEST_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+        let urls = (0..12)
+            .map(|idx| format!("https://executor-test.example/{idx}"))
+            .collect::<Vec<String>>();
+        for (idx, url) in urls.iter().enumerate() {
+            let mut content = vec!["quokka"; idx % 4 + 1];
+            content.extend(vec!["island"; idx]);
+            if idx % 5 == 0 {
+                content.push("wombat");
+            }
+            let content = content.join(" ");
+            let title = format!("marsupial {idx}");
+            url_index::main::insert(url, &content, &title, "", "", Language::English, Option::None, "").unwrap();
+            inverted_index::main::insert_by_content(url, &content, &title, "", "", Language::English);
+        }
+        segment::main::flush();
+
+        let query = query::main::parse("quokka wombat", &[Language::English]);
+        let options = SearchOptions {
+            fuzzy: false,
+            per_host: 0,
+            ..Default::default()
+        };
+        let exhaustive = main::execute(&query, &options, urls.len()).results;
+        assert_eq!(exhaustive.len(), urls.len());
+        for k in [0, 1, 3, 5] {
+            let hits = main::execute(&query, &options, k);
+            assert_eq!(hits.total_hits, urls.len());
+            let top = hits.results;
+            assert_eq!(top.len(), k);
+            for (result, expected) in top.iter().zip(exhaustive.iter()) {
+                assert_eq!(result.url, expected.url);
+                assert_eq!(result.score, expected.score);
+            }
+        }
+
+        for url in urls.iter() {
+            assert!(inverted_index::main::delete_document(url).unwrap());
+        }
+    }
+
+    #[test]
+    fn collapsing_keeps_the_best_pages_of_each_host() {
+        let _lock = url_index::TEST_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+        let urls = (0..5)
+            .map(|idx| format!("https://big.collapse-test.example/{idx}"))
+            .chain(["https://small.collapse-test.example/".to_string()])
+            .chain(["https://www.other-collapse-test.example/a".to_string()])
+            .collect::<Vec<String>>();
+        for (idx, url) in urls.iter().enumerate() {
+            let content = vec!["pangolin"; idx + 1].join(" ");
+            url_index::main::insert(url, &content, "scales", "", "", Language::English, Option::None, "").unwrap();
+            inverted_index::main::insert_by_content(url, &content, "scales", "", "", Language::English);
+        }
+        segment::main::flush();
+
+        let query = query::main::parse("pangolin", &[Language::English]);
+        let options = SearchOptions {
+            fuzzy: false,
+            per_host: 0,
+            ..Default::default()
+        };
+        let all = main::execute(&query, &options, urls.len()).results;
+        let mut host_counts = HashMap::new();
+        let expected = all
+            .into_iter()
+            .filter(|result| {
+                let count = host_counts.entry(url_index::main::get_host(&result.url)).or_insert(0);
+                *count += 1;
+                *count <= 2
+            })
+            .collect::<Vec<ResultScore>>();
+        let options = SearchOptions {
+            per_host: 2,
+            ..options
+        };
+        for k in 1..=expected.len() {
+            let hits = main::execute(&query, &options, k);
+            assert_eq!(hits.total_hits, 4);
+            let urls = hits.results.iter().map(|result| result.url.as_str()).collect::<Vec<&str>>();
+            let expected_urls = expected[..k].iter().map(|result| result.url.as_str()).collect::<Vec<&str>>();
+            assert_eq!(urls, expected_urls);
+        }
+        let results = main::execute(&query, &options, 4).results;
+        for result in results.iter() {
+            let expected = match result.url.contains("big.") {
+                true => 3,
+                false => 0,
+            };
+            assert_eq!(result.more_from_host, expected);
+        }
+
+        for url in urls.iter() {
+            assert!(inverted_index::main::delete_document(url).unwrap());
+        }
+    }
+
+    #[test]
+    fn facets_count_every_match_and_mime_type_filters() {
+        let _lock = url_index::TEST_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+        let docs = [
+            ("https://a.facet-test.example/1", "text/html", Language::English),
+            ("https://a.facet-test.example/2", "text/html", Language::French),
+            ("https://b.facet-test.example/1", "application/pdf", Language::English),
+        ];
+        for (url, mime_type, language) in docs {
+            url_index::main::insert(url, "axolotl", "axolotl", "", "", language, Option::None, mime_type).unwrap();
+            inverted_index::main::insert_by_content(url, "axolotl", "axolotl", "", "", language);
+        }
+        segment::main::flush();
+
+        let query = query::main::parse("axolotl", &[Language::English]);
+        let options = SearchOptions {
+            fuzzy: false,
+            per_host: 0,
+            ..Default::default()
+        };
+        // the facets cover every match even when only one result is scored
+        let hits = main::execute(&query, &options, 1);
+        let get_count = |counts: &[facets::FacetCount], value: &str| {
+            counts.iter().find(|count| count.value == value).map_or(0, |count| count.count)
+        };
+        assert_eq!(get_count(&hits.facets.host, "a.facet-test.example"), 2);
+        assert_eq!(get_count(&hits.facets.host, "b.facet-test.example"), 1);
+        assert_eq!(get_count(&hits.facets.mime_type, "text/html"), 2);
+        assert_eq!(get_count(&hits.facets.language, "fr"), 1);
+        assert_eq!(hits.facets.date.iter().map(|bucket| bucket.count).sum::<usize>(), 3);
+
+        let options = SearchOptions {
+            mime_types: vec!["Application/PDF".to_string()],
+            ..options
+        };
+        let hits = main::execute(&query, &options, 3);
+        assert_eq!(hits.total_hits, 1);
+        assert_eq!(hits.results[0].url, "https://b.facet-test.example/1");
+        assert_eq!(get_count(&hits.facets.host, "a.facet-test.example"), 0);
+
+        for (url, _, _) in docs {
+            assert!(inverted_index::main::delete_document(url).unwrap());
+        }
+    }
+
+    #[test]
+    fn explanation_adds_up_to_the_score() {
+        let _lock = url_index::TEST_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+        let url = "https://executor-test.example/explain";
+        let content = "numbat termite numbat";
+        url_index::main::insert(url, content, "numbat facts", "", "", Language::English, Option::None, "").unwrap();
+        inverted_index::main::insert_by_content(url, content, "numbat facts", "", "", Language::English);
+        segment::main::flush();
+
+        let query = query::main::parse("numbat termite", &[Language::English]);
+        let options = SearchOptions {
+            fuzzy: false,
+            explain: true,
+            ..Default::default()
+        };
+        let results = main::execute(&query, &options, 1).results;
+        let explanation = results[0].explanation.as_ref().unwrap();
+        assert_eq!(explanation.score, results[0].score);
+        assert_eq!(explanation.matched_groups, 2);
+        let term_score = explanation.terms.iter().map(|term| term.score).sum::<f64>();
+        assert!((explanation.term_score - term_score).abs() < 1e-9);
+        assert!((explanation.score - term_score * 2.0).abs() < 1e-9);
+        let numbat = explanation.terms.iter().find(|term| term.term == "numbat").unwrap();
+        let title = numbat.bm25f.fields.iter().find(|field| field.field == Field::Title).unwrap();
+        assert_eq!(title.term_freq, 1);
+        let content = numbat.bm25f.fields.iter().find(|field| field.field == Field::Content).unwrap();
+        assert_eq!(content.term_freq, 2);
+
+        let options = SearchOptions {
+            fuzzy: false,
+            ..Default::default()
+        };
+        assert!(main::execute(&query, &options, 1).results[0].explanation.is_none());
+        assert!(inverted_index::main::delete_document(url).unwrap());
+    }
+}
@@ -0,0 +1,201 @@
+use crate::language::Language;
+use crate::url_index::{self, DocMeta};
+use chrono::{DateTime, Datelike, Months, SecondsFormat, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::env;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DateInterval {
+    Day,
+    Month,
+    Year,
+}
+
+impl DateInterval {
+    pub fn from_name(name: &str) -> Option<DateInterval> {
+        match name.trim() {
+            "day" => Some(DateInterval::Day),
+            "month" => Some(DateInterval::Month),
+            "year" => Some(DateInterval::Year),
+            _ => Option::None,
+        }
+    }
+
+    // start of the bucket holding date
+    pub fn get_start(&self, date: DateTime<Utc>) -> DateTime<Utc> {
+        let (month, day) = match self {
+            DateInterval::Day => (date.month(), date.day()),
+            DateInterval::Month => (date.month(), 1),
+            DateInterval::Year => (1, 1),
+        };
+        Utc.with_ymd_and_hms(date.year(), month, day, 0, 0, 0).unwrap()
+    }
+
+    pub fn get_next(&self, start: DateTime<Utc>) -> DateTime<Utc> {
+        match self {
+            DateInterval::Day => start + chrono::Duration::days(1),
+            DateInterval::Month => start + Months::new(1),
+            DateInterval::Year => start + Months::new(12),
+        }
+    }
+
+    pub fn get_label(&self, start: DateTime<Utc>) -> String {
+        let format = match self {
+            DateInterval::Day => "%Y-%m-%d",
+            DateInterval::Month => "%Y-%m",
+            DateInterval::Year => "%Y",
+        };
+        start.format(format).to_string()
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FacetCount {
+    pub value: String,
+    pub count: usize,
+}
+
+// `from` and `to` are the after and before filters selecting the bucket, both inclusive
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DateBucket {
+    pub value: String,
+    pub from: String,
+    pub to: String,
+    pub count: usize,
+}
+
+// counts over every matching document, the value of each is what its filter takes
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Facets {
+    pub host: Vec<FacetCount>,
+    pub language: Vec<FacetCount>,
+    pub mime_type: Vec<FacetCount>,
+    pub date: Vec<DateBucket>,
+}
+
+// counts of a match set by interned value, resolved to names once at the end
+pub struct FacetCounter {
+    pub interval: DateInterval,
+    pub hosts: HashMap<u32, usize>,
+    pub languages: HashMap<Language, usize>,
+    pub mime_types: HashMap<u32, usize>,
+    pub dates: HashMap<DateTime<Utc>, usize>,
+}
+
+impl FacetCounter {
+    pub fn new(interval: DateInterval) -> FacetCounter {
+        FacetCounter {
+            interval,
+            hosts: HashMap::new(),
+            languages: HashMap::new(),
+            mime_types: HashMap::new(),
+            dates: HashMap::new(),
+        }
+    }
+
+    pub fn add(&mut self, meta: &DocMeta) {
+        *self.hosts.entry(meta.host).or_insert(0) += 1;
+        *self.languages.entry(meta.language).or_insert(0) += 1;
+        *self.mime_types.entry(meta.mime_type).or_insert(0) += 1;
+        let start = self.interval.get_start(meta.get_date());
+        *self.dates.entry(start).or_insert(0) += 1;
+    }
+}
+
+pub mod main {
+    use super::*;
+
+    fn get_max_values() -> usize {
+        env::var("FACET_MAX_VALUES")
+            .unwrap_or(String::from("10"))
+            .parse::<usize>()
+            .unwrap()
+    }
+
+    fn get_max_date_buckets() -> usize {
+        env::var("FACET_MAX_DATE_BUCKETS")
+            .unwrap_or(String::from("24"))
+            .parse::<usize>()
+            .unwrap()
+    }
+
+    // most frequent first, ties by value
+    fn get_top_values(counts: impl Iterator<Item = (String, usize)>) -> Vec<FacetCount> {
+        let mut values = counts
+            .filter(|(value, _)| !value.is_empty())
+            .map(|(value, count)| FacetCount { value, count })
+            .collect::<Vec<FacetCount>>();
+        values.sort_by(|a, b| b.count.cmp(&a.count).then(a.value.cmp(&b.value)));
+        values.truncate(get_max_values());
+        values
+    }
+
+    // the newest buckets in date order
+    pub fn get_date_buckets(counter: &FacetCounter) -> Vec<DateBucket> {
+        let mut starts = counter.dates.keys().copied().collect::<Vec<DateTime<Utc>>>();
+        starts.sort();
+        let skip = starts.len().saturating_sub(get_max_date_buckets());
+        starts
+            .into_iter()
+            .skip(skip)
+            .map(|start| {
+                let end = counter.interval.get_next(start) - chrono::Duration::milliseconds(1);
+                DateBucket {
+                    value: counter.interval.get_label(start),
+                    from: start.to_rfc3339_opts(SecondsFormat::Millis, true),
+                    to: end.to_rfc3339_opts(SecondsFormat::Millis, true),
+                    count: counter.dates[&start],
+                }
+            })
+            .collect()
+    }
+
+    pub fn get_facets(counter: &FacetCounter) -> Facets {
+        let meta_columns = url_index::META_COLUMNS.read().unwrap();
+        let get_values = |counts: &HashMap<u32, usize>| {
+            get_top_values(
+                counts
+                    .iter()
+                    .map(|(id, count)| (meta_columns.get_value(*id).to_string(), *count)),
+            )
+        };
+        Facets {
+            host: get_values(&counter.hosts),
+            language: get_top_values(
+                counter
+                    .languages
+                    .iter()
+                    .map(|(language, count)| (language.code().to_string(), *count)),
+            ),
+            mime_type: get_values(&counter.mime_types),
+            date: get_date_buckets(counter),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recency;
+
+    #[test]
+    fn date_buckets_cover_the_whole_interval() {
+        let date = recency::main::parse_date("2024-02-29T13:45:00Z").unwrap();
+        let month = DateInterval::Month;
+        let start = month.get_start(date);
+        assert_eq!(month.get_label(start), "2024-02");
+        assert_eq!(month.get_next(start), recency::main::parse_date("2024-03-01").unwrap());
+        let year = DateInterval::Year;
+        assert_eq!(year.get_next(year.get_start(date)), recency::main::parse_date("2025-01-01").unwrap());
+        let day = DateInterval::Day;
+        assert_eq!(day.get_label(day.get_start(date)), "2024-02-29");
+
+        let mut counter = FacetCounter::new(month);
+        counter.dates.insert(start, 3);
+        let buckets = main::get_date_buckets(&counter);
+        assert_eq!(buckets[0].from, "2024-02-01T00:00:00.000Z");
+        assert_eq!(buckets[0].to, "2024-02-29T23:59:59.999Z");
+        assert_eq!(recency::main::parse_date(&buckets[0].to).unwrap() + chrono::Duration::milliseconds(1), month.get_next(start));
+    }
+}
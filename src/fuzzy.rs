@@ -0,0 +1,67 @@
+use std::env;
+
+// Levenshtein automaton over a fixed query term, each state is a row of the edit distance matrix
+// so a dictionary term can be rejected as soon as no row entry is within the allowed distance
+pub struct LevenshteinAutomaton {
+    query: Vec<char>,
+    max_distance: usize,
+}
+
+impl LevenshteinAutomaton {
+    pub fn new(query: &str, max_distance: usize) -> LevenshteinAutomaton {
+        LevenshteinAutomaton {
+            query: query.chars().collect(),
+            max_distance,
+        }
+    }
+
+    pub fn start(&self) -> Vec<usize> {
+        (0..=self.query.len()).collect()
+    }
+
+    pub fn step(&self, state: &[usize], c: char) -> Vec<usize> {
+        let mut next_state = vec![state[0] + 1];
+        for (idx, query_char) in self.query.iter().enumerate() {
+            let cost = if *query_char == c { 0 } else { 1 };
+            let value = (next_state[idx] + 1)
+                .min(state[idx + 1] + 1)
+                .min(state[idx] + cost);
+            next_state.push(value);
+        }
+        next_state
+    }
+
+    pub fn is_match(&self, state: &[usize]) -> bool {
+        state[state.len() - 1] <= self.max_distance
+    }
+
+    pub fn can_match(&self, state: &[usize]) -> bool {
+        state.iter().any(|value| *value <= self.max_distance)
+    }
+}
+
+pub mod main {
+    use super::*;
+
+    // short terms have too many neighbours to be corrected safely, longer ones get more room
+    pub fn get_max_distance(term: &str) -> usize {
+        let max_distance = env::var("FUZZY_MAX_DISTANCE")
+            .unwrap_or(String::from("2"))
+            .parse::<usize>()
+            .unwrap();
+        let distance = match term.chars().count() {
+            0..=2 => 0,
+            3..=5 => 1,
+            _ => 2,
+        };
+        distance.min(max_distance)
+    }
+
+    pub fn get_fuzzy_weight(distance: usize) -> f64 {
+        let fuzzy_weight = env::var("FUZZY_TERM_WEIGHT")
+            .unwrap_or(String::from("0.5"))
+            .parse::<f64>()
+            .unwrap();
+        fuzzy_weight.powi(distance as i32)
+    }
+}
@@ -0,0 +1,373 @@
+use crate::inverted_index::Field;
+use crate::postings::{self, PostingList};
+use crate::url_index::FieldCount;
+use crate::wal;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+
+// layout, integers little endian and strings as a u32 byte length and utf-8:
+//   header      MAGIC, u32 version
+//   doc store   per doc id: url, content hash, 5 x u32 field lengths
+//   postings    per term: u8 field count, then per field: u8 field id, u32 count, the doc
+//               ids as a PostingList encodes them (bitpacked blocks of gaps and a varint tail)
+//               and its bitpacked term frequencies, each behind a u32 byte length
+//   dictionary  per term in ascending order: text, surface, u64 postings offset
+//   footer      u64 doc store, postings and dictionary offsets, u32 doc and term counts, MAGIC
+// the footer lets the file be written in one pass, a reader starts from it
+const MAGIC: &[u8; 8] = b"SEINVIDX";
+// 2 stems url terms, 3 indexes top level domains and single label hosts as sites, 4 indexes
+// CJK unigrams, 5 keeps term frequencies, 6 stores postings block encoded. files of older
+// versions are analyzed again
+const VERSION: u32 = 6;
+const FOOTER_LEN: usize = 8 * 3 + 4 * 2 + 8;
+
+pub struct DocEntry {
+    pub url: String,
+    // md5 of the content, a stored document with another hash has changed since the write
+    pub hash: String,
+    pub field_lengths: FieldCount,
+}
+
+pub struct TermEntry {
+    pub text: String,
+    pub surface: String,
+    // (doc id, term frequency) per field, ids index into IndexFile::docs
+    pub field_docs: Vec<(Field, Vec<(u32, u32)>)>,
+}
+
+#[derive(Default)]
+pub struct IndexFile {
+    pub docs: Vec<DocEntry>,
+    // in ascending order of text
+    pub terms: Vec<TermEntry>,
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8], pos: usize) -> Reader<'a> {
+        Reader { bytes, pos }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Box<dyn Error>> {
+        let end = self.pos.checked_add(len).filter(|end| *end <= self.bytes.len());
+        let Some(end) = end else {
+            return Err(format!("index file truncated at byte {}", self.pos).into());
+        };
+        let bytes = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, Box<dyn Error>> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn read_u32(&mut self) -> Result<u32, Box<dyn Error>> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn read_u64(&mut self) -> Result<u64, Box<dyn Error>> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into()?))
+    }
+
+    fn read_string(&mut self) -> Result<String, Box<dyn Error>> {
+        let len = self.read_u32()? as usize;
+        Ok(String::from_utf8(self.take(len)?.to_vec())?)
+    }
+}
+
+pub mod main {
+    use super::*;
+
+    pub fn get_field_id(field: Field) -> u8 {
+        match field {
+            Field::Url => 0,
+            Field::Site => 1,
+            Field::Title => 2,
+            Field::Headings => 3,
+            Field::Highlighted => 4,
+            Field::Content => 5,
+        }
+    }
+
+    pub fn get_field(id: u8) -> Option<Field> {
+        match id {
+            0 => Some(Field::Url),
+            1 => Some(Field::Site),
+            2 => Some(Field::Title),
+            3 => Some(Field::Headings),
+            4 => Some(Field::Highlighted),
+            5 => Some(Field::Content),
+            _ => Option::None,
+        }
+    }
+
+    fn write_string(out: &mut impl Write, value: &str) -> std::io::Result<()> {
+        out.write_all(&(value.len() as u32).to_le_bytes())?;
+        out.write_all(value.as_bytes())
+    }
+
+    fn write_buf(out: &mut impl Write, buf: &[u8], pos: &mut u64) -> std::io::Result<()> {
+        *pos += buf.len() as u64;
+        out.write_all(buf)
+    }
+
+    // returns the number of bytes written
+    pub fn encode(index_file: &IndexFile, out: &mut impl Write) -> std::io::Result<u64> {
+        let mut pos = 0u64;
+        let mut header = MAGIC.to_vec();
+        header.extend_from_slice(&VERSION.to_le_bytes());
+        write_buf(out, &header, &mut pos)?;
+
+        let doc_store_offset = pos;
+        for doc in index_file.docs.iter() {
+            let mut buf = Vec::new();
+            write_string(&mut buf, &doc.url)?;
+            write_string(&mut buf, &doc.hash)?;
+            for field in Field::SCORED {
+                buf.extend_from_slice(&(doc.field_lengths.get(field) as u32).to_le_bytes());
+            }
+            write_buf(out, &buf, &mut pos)?;
+        }
+
+        let postings_offset = pos;
+        let mut term_offsets = Vec::with_capacity(index_file.terms.len());
+        for term in index_file.terms.iter() {
+            term_offsets.push(pos - postings_offset);
+            let mut buf = vec![term.field_docs.len() as u8];
+            for (field, docs) in term.field_docs.iter() {
+                buf.push(get_field_id(*field));
+                buf.extend_from_slice(&(docs.len() as u32).to_le_bytes());
+                let (doc_ids, freqs): (Vec<u32>, Vec<u32>) = docs.iter().copied().unzip();
+                let list = PostingList::from_sorted_with_freqs(&doc_ids, &freqs);
+                let (data, freq_data) = list.get_bytes();
+                for bytes in [data, freq_data] {
+                    buf.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+                    buf.extend_from_slice(bytes);
+                }
+            }
+            write_buf(out, &buf, &mut pos)?;
+        }
+
+        let dictionary_offset = pos;
+        for (term, offset) in index_file.terms.iter().zip(term_offsets) {
+            let mut buf = Vec::new();
+            write_string(&mut buf, &term.text)?;
+            write_string(&mut buf, &term.surface)?;
+            buf.extend_from_slice(&offset.to_le_bytes());
+            write_buf(out, &buf, &mut pos)?;
+        }
+
+        let mut footer = Vec::new();
+        footer.extend_from_slice(&doc_store_offset.to_le_bytes());
+        footer.extend_from_slice(&postings_offset.to_le_bytes());
+        footer.extend_from_slice(&dictionary_offset.to_le_bytes());
+        footer.extend_from_slice(&(index_file.docs.len() as u32).to_le_bytes());
+        footer.extend_from_slice(&(index_file.terms.len() as u32).to_le_bytes());
+        footer.extend_from_slice(MAGIC);
+        write_buf(out, &footer, &mut pos)?;
+        Ok(pos)
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<IndexFile, Box<dyn Error>> {
+        if bytes.len() < 12 + FOOTER_LEN || &bytes[..8] != MAGIC || &bytes[bytes.len() - 8..] != MAGIC {
+            return Err("not an inverted index file".into());
+        }
+        let version = Reader::new(bytes, 8).read_u32()?;
+        if version != VERSION {
+            return Err(format!("unsupported inverted index file version {version}").into());
+        }
+        let mut footer = Reader::new(bytes, bytes.len() - FOOTER_LEN);
+        let doc_store_offset = footer.read_u64()? as usize;
+        let postings_offset = footer.read_u64()? as usize;
+        let dictionary_offset = footer.read_u64()? as usize;
+        let doc_count = footer.read_u32()? as usize;
+        let term_count = footer.read_u32()? as usize;
+
+        let mut reader = Reader::new(bytes, doc_store_offset);
+        // counts come from the file, capacity is bounded by what it can hold
+        let mut docs = Vec::with_capacity(doc_count.min(bytes.len()));
+        for _ in 0..doc_count {
+            let url = reader.read_string()?;
+            let hash = reader.read_string()?;
+            let mut lengths = [0u64; 5];
+            for length in lengths.iter_mut() {
+                *length = reader.read_u32()? as u64;
+            }
+            let [url_len, title, headings, highlighted, content] = lengths;
+            let field_lengths = FieldCount {
+                url: url_len,
+                title,
+                headings,
+                highlighted,
+                content,
+            };
+            docs.push(DocEntry {
+                url,
+                hash,
+                field_lengths,
+            });
+        }
+
+        let mut dictionary = Reader::new(bytes, dictionary_offset);
+        let mut terms = Vec::with_capacity(term_count.min(bytes.len()));
+        for _ in 0..term_count {
+            let text = dictionary.read_string()?;
+            let surface = dictionary.read_string()?;
+            let offset = dictionary.read_u64()? as usize;
+            let mut postings = Reader::new(bytes, postings_offset.saturating_add(offset));
+            let field_count = postings.read_u8()?;
+            let mut field_docs = Vec::with_capacity(field_count as usize);
+            for _ in 0..field_count {
+                let id = postings.read_u8()?;
+                let field = get_field(id).ok_or(format!("unknown field id {id} for term {text}"))?;
+                let len = postings.read_u32()? as usize;
+                let data_len = postings.read_u32()? as usize;
+                let data = postings.take(data_len)?;
+                let freq_len = postings.read_u32()? as usize;
+                let freq_data = postings.take(freq_len)?;
+                let docs = postings::main::decode(len, data, freq_data)
+                    .ok_or(format!("damaged postings for term {text}"))?;
+                if let Some((doc_id, _)) = docs.last().filter(|(doc_id, _)| *doc_id as usize >= doc_count) {
+                    return Err(format!("doc id {doc_id} out of range for term {text}").into());
+                }
+                field_docs.push((field, docs));
+            }
+            terms.push(TermEntry {
+                text,
+                surface,
+                field_docs,
+            });
+        }
+        Ok(IndexFile { docs, terms })
+    }
+
+    // written next to the target and renamed over it, a crash never leaves half a file
+    pub fn write(filepath: &str, index_file: &IndexFile) -> Result<u64, Box<dyn Error + Send + Sync>> {
+        let temp_filepath = format!("{filepath}.temp");
+        let mut out = BufWriter::new(File::create(&temp_filepath)?);
+        let len = encode(index_file, &mut out)?;
+        out.flush()?;
+        out.get_ref().sync_all()?;
+        drop(out);
+        fs::rename(&temp_filepath, filepath)?;
+        // the rename only survives a crash once the directory entry is on disk
+        wal::main::sync_parent_dir(filepath)?;
+        Ok(len)
+    }
+
+    // none when there is no file yet. the whole file is decoded once at startup into the
+    // segments, lookups never read it
+    pub fn read(filepath: &str) -> Result<Option<IndexFile>, Box<dyn Error>> {
+        match fs::read(filepath) {
+            Ok(bytes) => decode(&bytes).map(Some),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Option::None),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn index_file_round_trips_and_rejects_truncation() {
+        let index_file = IndexFile {
+            docs: vec![
+                DocEntry {
+                    url: "https://a.example/".to_string(),
+                    hash: "123".to_string(),
+                    field_lengths: FieldCount {
+                        url: 2,
+                        title: 3,
+                        headings: 0,
+                        highlighted: 1,
+                        content: 40,
+                    },
+                },
+                DocEntry {
+                    url: "https://b.example/ünïcode".to_string(),
+                    hash: "456".to_string(),
+                    field_lengths: FieldCount::default(),
+                },
+            ],
+            terms: vec![
+                TermEntry {
+                    text: "rust".to_string(),
+                    surface: "Rust".to_string(),
+                    field_docs: vec![(Field::Title, vec![(0, 1)]), (Field::Content, vec![(0, 3), (1, 1)])],
+                },
+                TermEntry {
+                    text: "zebra".to_string(),
+                    surface: "zebras".to_string(),
+                    field_docs: vec![(Field::Site, vec![(1, 1)])],
+                },
+            ],
+        };
+        let mut bytes = Vec::new();
+        let len = main::encode(&index_file, &mut bytes).unwrap();
+        assert_eq!(len as usize, bytes.len());
+
+        let decoded = main::decode(&bytes).unwrap();
+        assert_eq!(decoded.docs.len(), 2);
+        assert_eq!(decoded.docs[1].url, "https://b.example/ünïcode");
+        assert_eq!(decoded.docs[0].field_lengths.content, 40);
+        assert_eq!(decoded.docs[0].field_lengths.title, 3);
+        assert_eq!(decoded.terms[0].surface, "Rust");
+        assert_eq!(decoded.terms[0].field_docs, index_file.terms[0].field_docs);
+        assert_eq!(decoded.terms[1].field_docs, vec![(Field::Site, vec![(1, 1)])]);
+
+        assert!(main::decode(&bytes[..bytes.len() - 1]).is_err());
+        let mut corrupt = bytes.clone();
+        let dictionary_offset = corrupt.len() - FOOTER_LEN + 16;
+        corrupt[dictionary_offset..dictionary_offset + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(main::decode(&corrupt).is_err());
+    }
+
+    #[test]
+    fn written_file_stores_block_encoded_postings() {
+        let docs = (0..300)
+            .map(|idx| DocEntry {
+                url: format!("https://a.example/{idx}"),
+                hash: idx.to_string(),
+                field_lengths: FieldCount::default(),
+            })
+            .collect::<Vec<DocEntry>>();
+        let postings = (0..300).step_by(2).map(|doc_id| (doc_id, doc_id % 5 + 1)).collect::<Vec<(u32, u32)>>();
+        let index_file = IndexFile {
+            docs,
+            terms: vec![TermEntry {
+                text: "rust".to_string(),
+                surface: "rust".to_string(),
+                field_docs: vec![(Field::Content, postings.clone())],
+            }],
+        };
+        let dir = std::env::temp_dir().join(format!("search-engine-index-file-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let filepath = dir.join("index.bin").to_string_lossy().to_string();
+
+        let len = main::write(&filepath, &index_file).unwrap();
+        let mut bytes = Vec::new();
+        let term_len = main::encode(&IndexFile { docs: vec![], terms: index_file.terms }, &mut bytes).unwrap()
+            - main::encode(&IndexFile::default(), &mut Vec::new()).unwrap();
+        // well under the 8 bytes a raw doc id and frequency would take
+        assert!(term_len < postings.len() as u64 * 2, "{term_len} bytes");
+        let decoded = main::read(&filepath).unwrap().unwrap();
+        assert_eq!(fs::metadata(&filepath).unwrap().len(), len);
+        assert_eq!(decoded.terms[0].field_docs, vec![(Field::Content, postings)]);
+        assert!(fs::metadata(format!("{filepath}.temp")).is_err());
+
+        // ids past the doc store are rejected
+        let mut short = fs::read(&filepath).unwrap();
+        let doc_count_offset = short.len() - FOOTER_LEN + 24;
+        short[doc_count_offset..doc_count_offset + 4].copy_from_slice(&100u32.to_le_bytes());
+        assert!(main::decode(&short).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,368 +1,957 @@
+use crate::analyzer;
+use crate::executor;
+use crate::facets::Facets;
+use crate::fuzzy::{self, LevenshteinAutomaton};
+use crate::index_file::{self, DocEntry, IndexFile, TermEntry};
+use crate::language::Language;
+use crate::query::{self, QueryTerm, SearchOptions, TermKind};
+use crate::segment::{self, PostingsBuilder, Snapshot};
+use crate::snippet;
+use crate::stats::{self, CorpusStats, TermStats};
 use crate::url_index;
 use float_ord::FloatOrd;
 use lazy_static::lazy_static;
 use serde::{Deserialize, Serialize};
 use std::cmp;
-use std::collections::BinaryHeap;
-use std::collections::HashMap;
-use std::io::Write;
-use std::io::{BufRead, BufReader};
-use std::thread;
-use std::{
-    collections::HashSet,
-    sync::{Arc, RwLock},
-};
-use std::{env, error::Error, fs, fs::File};
-
-pub struct Node {
-    text: String,
-    urls: HashSet<String>,
-    left: Box<Option<Node>>,
-    right: Box<Option<Node>>,
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::Instant;
+use std::{env, error::Error};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Field {
+    Url,
+    Site,
+    Title,
+    Headings,
+    Highlighted,
+    Content,
+}
+
+impl Field {
+    pub const SCORED: [Field; 5] = [
+        Field::Url,
+        Field::Title,
+        Field::Headings,
+        Field::Highlighted,
+        Field::Content,
+    ];
+
+    pub fn weight(&self) -> u8 {
+        match self {
+            Field::Url => 8,
+            Field::Title => 6,
+            Field::Headings => 4,
+            Field::Highlighted => 2,
+            Field::Content => 1,
+            Field::Site => 0,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct FieldParams {
+    pub boost: f64,
+    pub b: f64,
+}
+
+#[derive(Clone)]
+pub struct Bm25Config {
+    pub k1: f64,
+    pub fields: HashMap<Field, FieldParams>,
+}
+
+// one field of one document for one query term, lengths are in tokens
+pub struct FieldStats {
+    pub field: Field,
+    pub term_freq: u64,
+    pub len: u64,
+    pub avg_len: f64,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ResultScore {
-    url: String,
-    title: String,
-    score: f64,
+    pub url: String,
+    pub title: String,
+    pub score: f64,
+    // html fragments of the stored text with matched words in <mark>, only for the returned page
+    #[serde(default)]
+    pub snippets: Vec<String>,
+    // matching pages of the same host left out by collapsing
+    #[serde(default)]
+    pub more_from_host: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub explanation: Option<Explanation>,
+}
+
+// how one field of a document contributed to a term's weighted tf
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FieldExplanation {
+    pub field: Field,
+    pub term_freq: u64,
+    pub len: u64,
+    pub avg_len: f64,
+    pub b: f64,
+    pub boost: f64,
+    // 1 - b + b * len / avg_len
+    pub length_norm: f64,
+    // boost * term_freq / length_norm
+    pub weighted_tf: f64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Bm25fExplanation {
+    // idf * (k1 + 1) * tf / (k1 + tf)
+    pub score: f64,
+    pub idf: f64,
+    pub doc_freq: u64,
+    pub total_count: u64,
+    pub k1: f64,
+    // sum of the fields' weighted tf
+    pub tf: f64,
+    pub fields: Vec<FieldExplanation>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TermExplanation {
+    pub term: String,
+    pub field: Option<Field>,
+    // the query word it was expanded from, fuzzy variants and synonyms share it
+    pub group: usize,
+    // below 1 for fuzzy, wildcard and synonym expansions
+    pub weight: f64,
+    pub language_boost: f64,
+    // bm25f score * weight * language_boost
+    pub score: f64,
+    pub bm25f: Bm25fExplanation,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Explanation {
+    // sum of the term scores * matched_groups * recency_boost
+    pub score: f64,
+    pub term_score: f64,
+    // distinct query words matched, the multi-term boost
+    pub matched_groups: usize,
+    // gaussian decay on the page's published date
+    pub recency_boost: f64,
+    pub terms: Vec<TermExplanation>,
+}
+
+pub struct SearchHits {
+    pub results: Vec<ResultScore>,
+    // every matching document, not only the scored top-k
+    pub total_hits: usize,
+    // query terms after expansion, what snippets highlight
+    pub terms: HashSet<String>,
+    // over every matching document, before collapsing
+    pub facets: Facets,
 }
 
 lazy_static! {
-    static ref root: Arc<RwLock<Option<Node>>> = Arc::new(RwLock::new(Option::None));
+    pub static ref BM25_CONFIG: Bm25Config = main::get_bm25_config();
 }
 
 pub mod main {
     use super::*;
+    // the saved postings of documents that are still current, returns those documents by url
+    fn load(index_file: IndexFile, hashes: &HashMap<String, String>) -> HashMap<String, DocEntry> {
+        let IndexFile { docs, terms } = index_file;
+        let is_current = docs
+            .iter()
+            .map(|doc| hashes.get(&doc.url) == Some(&doc.hash))
+            .collect::<Vec<bool>>();
+        let doc_ids = docs
+            .iter()
+            .map(|doc| segment::main::add_doc_id(&doc.url))
+            .collect::<Vec<u32>>();
+        let mut postings = HashMap::new();
+        for term in terms {
+            let mut term_postings = PostingsBuilder::new(&term.surface);
+            for (field, file_docs) in term.field_docs {
+                for (file_doc_id, freq) in file_docs.into_iter().filter(|(doc_id, _)| is_current[*doc_id as usize]) {
+                    term_postings.add_freq(doc_ids[file_doc_id as usize], field, freq);
+                }
+            }
+            if !term_postings.is_empty() {
+                postings.insert(term.text, term_postings);
+            }
+        }
+        let current_ids = doc_ids
+            .into_iter()
+            .zip(is_current.iter())
+            .filter(|(_, is_current)| **is_current)
+            .map(|(doc_id, _)| doc_id)
+            .collect();
+        segment::main::replace_segments(postings, current_ids);
+        docs.into_iter()
+            .zip(is_current)
+            .filter(|(_, is_current)| *is_current)
+            .map(|(doc, _)| (doc.url.to_string(), doc))
+            .collect()
+    }
+
+    // loads the saved index and only analyzes the documents it doesn't have or has an older
+    // version of, without a saved index every document of the url index is analyzed
     pub fn index() -> Result<(), Box<dyn Error>> {
-        let filepath = &env::var("URL_INDEX_FILE_PATH")?;
-        let file_data = File::open(filepath);
-        if file_data.is_err() {
-            println!("err while loading index : {:?}", file_data);
-            return Ok(());
+        let started = Instant::now();
+        let index_file = match env::var("INVERTED_INDEX_FILE_PATH") {
+            Ok(filepath) => index_file::main::read(&filepath).unwrap_or_else(|err| {
+                println!("err while loading inverted index : {:?}", err);
+                Option::None
+            }),
+            Err(_) => Option::None,
+        };
+        let saved_count = index_file.as_ref().map_or(0, |index_file| index_file.docs.len());
+        let hashes = url_index::main::get_doc_hashes().into_iter().collect::<HashMap<String, String>>();
+        let saved_docs = load(index_file.unwrap_or_default(), &hashes);
+        let mut field_lengths = HashMap::new();
+        let mut changed_urls = Vec::new();
+        for url in hashes.into_keys() {
+            match saved_docs.get(&url) {
+                Some(doc) => {
+                    field_lengths.insert(url, doc.field_lengths);
+                }
+                None => changed_urls.push(url),
+            }
         }
-        let file_data = file_data.unwrap();
-        let reader = BufReader::new(file_data);
-        for line in reader.lines() {
-            if line.is_err() {
-                println!("buffer read line error : {:?}", line);
+        for url in changed_urls.iter() {
+            let Some(node) = url_index::main::get_by_url(url) else {
                 continue;
-            }
-            let content = line.unwrap();
-            let content_data = content.split("$$==$$=$$").collect::<Vec<&str>>();
-            match content_data.len() {
-                5 => (),
-                _ => continue,
-            }
-            let [url, title, headings, highlighted, content]: [&str; 5] =
-                content_data[..5].try_into().unwrap();
-            // println!("{url} == {content} == {title} == {headings} == {highlighted}");
-            insert_by_content(url, content, title, headings, highlighted);
+            };
+            insert_by_content(url, &node.content, &node.title, &node.headings, &node.highlighted, node.language);
+            let doc_stats = stats::main::get_doc_stats(
+                url,
+                &node.content,
+                &node.title,
+                &node.headings,
+                &node.highlighted,
+                node.language,
+            );
+            field_lengths.insert(url.to_string(), doc_stats.field_lengths);
         }
-        println!("=== INVERTED INDEXING FINISHED ===");
+        segment::main::flush();
+        url_index::main::set_field_lengths(field_lengths);
+        url_index::main::rebuild_stats();
+        println!(
+            "=== INVERTED INDEXING FINISHED === loaded : {}, analyzed : {}, dropped : {}, took : {:?}",
+            saved_docs.len(),
+            changed_urls.len(),
+            saved_count - saved_docs.len(),
+            started.elapsed()
+        );
         Ok(())
     }
 
-    fn write_to_file(url: &str, content: &str) -> Result<(), Box<dyn Error>> {
+    // postings of urls missing from the url index are left out, the next load analyzes
+    // documents that are missing from the file
+    pub fn write_to_file() -> Result<(), Box<dyn Error + Send + Sync>> {
+        println!("writing inverted index to file");
         let filepath = &env::var("INVERTED_INDEX_FILE_PATH")?;
-        let mut file_data = fs::OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(filepath)?;
-        let write_content = format!("{}$$==$$=$${}\n", url, content);
-        let _ = file_data.write(write_content.as_bytes());
+        // hashes are read before the postings, a page recrawled in between then has a hash
+        // older than its postings and the next load analyzes it again
+        let docs = url_index::main::get_doc_entries();
+        segment::main::flush();
+        let snapshot = segment::main::get_snapshot();
+        // ids in the file are positions in its doc store
+        let doc_ids = docs
+            .iter()
+            .enumerate()
+            .filter_map(|(file_doc_id, doc)| Some((segment::main::get_doc_id(&doc.url)?, file_doc_id as u32)))
+            .collect::<HashMap<u32, u32>>();
+        // (doc id, term frequency) per field
+        type FieldDocs = HashMap<Field, Vec<(u32, u32)>>;
+        // segments are sorted on their own, a term of several segments is written once
+        let mut entries: BTreeMap<&str, (&str, FieldDocs)> = BTreeMap::new();
+        snapshot.visit_terms("", |term, reader, postings| {
+            let (_, field_docs) = entries.entry(term).or_insert((&postings.surface, HashMap::new()));
+            for (field, docs) in postings.field_docs.iter() {
+                let ids = docs
+                    .iter()
+                    .zip(docs.get_freqs())
+                    .filter(|(doc_id, _)| reader.is_live(*doc_id))
+                    .filter_map(|(doc_id, freq)| Some((*doc_ids.get(&doc_id)?, freq)));
+                field_docs.entry(*field).or_default().extend(ids);
+            }
+        });
+        let terms = entries
+            .into_iter()
+            .filter_map(|(text, (surface, field_docs))| {
+                let mut field_docs = field_docs
+                    .into_iter()
+                    .filter(|(_, ids)| !ids.is_empty())
+                    .map(|(field, mut ids)| {
+                        ids.sort();
+                        (field, ids)
+                    })
+                    .collect::<Vec<(Field, Vec<(u32, u32)>)>>();
+                field_docs.sort_by_key(|(field, _)| index_file::main::get_field_id(*field));
+                (!field_docs.is_empty()).then(|| TermEntry {
+                    text: text.to_string(),
+                    surface: surface.to_string(),
+                    field_docs,
+                })
+            })
+            .collect();
+        let index_file = IndexFile { docs, terms };
+        let len = index_file::main::write(filepath, &index_file)?;
+        println!(
+            "inverted index written => docs : {}, terms : {}, bytes : {len}",
+            index_file.docs.len(),
+            index_file.terms.len()
+        );
         Ok(())
     }
 
-    fn insert_helper(node: &mut Option<Node>, text: &str, url: &str) -> Option<Node> {
-        if node.is_none() {
-            let new_node = Some(Node {
-                text: String::from(text),
-                urls: HashSet::from([String::from(url)]),
-                left: Box::new(Option::None),
-                right: Box::new(Option::None),
+    // corpus stats of every searchable term, the same numbers adding each document would give
+    pub fn get_term_stats() -> HashMap<String, TermStats> {
+        let mut terms: HashMap<String, TermStats> = HashMap::new();
+        segment::main::get_snapshot().visit_terms("", |term, reader, postings| {
+            let term_stats = terms.entry(term.to_string()).or_insert(TermStats {
+                doc_freq: 0,
+                field_doc_freq: HashMap::new(),
             });
-            return new_node;
-        }
-        let node = node.as_mut().unwrap();
-        if node.text == text {
-            node.urls.insert(url.to_string());
-            return Option::None;
-        } else if *node.text >= *text {
-            let resp = insert_helper(&mut node.right, text, url);
-            match resp {
-                Some(next_node) => {
-                    *node.right = Option::Some(next_node);
-                    return Option::None;
+            term_stats.doc_freq += reader.get_doc_freq(postings, Option::None) as u64;
+            for (field, _) in postings.field_docs.iter().filter(|(field, _)| *field != Field::Site) {
+                let doc_freq = reader.get_doc_freq(postings, Some(*field)) as u64;
+                if doc_freq > 0 {
+                    *term_stats.field_doc_freq.entry(*field).or_insert(0) += doc_freq;
                 }
-                _ => return Option::None,
             }
-        } else {
-            let resp = insert_helper(&mut node.left, text, url);
-            match resp {
-                Some(next_node) => {
-                    *node.left = Option::Some(next_node);
-                    return Option::None;
-                }
-                _ => return Option::None,
+        });
+        terms.retain(|_, term_stats| term_stats.doc_freq > 0);
+        terms
+    }
+
+    // returns (surface form, term) pairs of a field, url parts and hosts are never stemmed
+    pub fn get_field_tokens(field: Field, document: &str, language: Language) -> Vec<(String, String)> {
+        match field {
+            // stemmed in the page language like the other fields, unscoped query terms are
+            // stemmed before they are matched against it
+            Field::Url => document
+                .split(|c: char| !c.is_alphanumeric())
+                .filter(|word| !word.eq_ignore_ascii_case("http") && !word.eq_ignore_ascii_case("https"))
+                .filter_map(|word| analyzer::ANALYZER.filter_with(word, language, &analyzer::ANALYZER.filters))
+                .collect(),
+            Field::Site => {
+                // "docs.example.com" is indexed as itself, "example.com" and "com" so all of them
+                // match `site:`, a single label host like "localhost" as itself
+                let host = url_index::main::get_host(document);
+                let labels = host.split('.').filter(|label| !label.is_empty()).collect::<Vec<&str>>();
+                (0..labels.len())
+                    .map(|idx| labels[idx..].join("."))
+                    .map(|site| (site.to_string(), site))
+                    .collect()
             }
+            _ => analyzer::ANALYZER.analyze(document, language),
         }
     }
 
+    pub fn get_field_words(field: Field, document: &str, language: Language) -> Vec<String> {
+        get_field_tokens(field, document, language)
+            .into_iter()
+            .map(|(_, term)| term)
+            .collect()
+    }
+
+    // replaces the stored version of the document, searchable after the next refresh
     pub fn insert_by_content(
         url: &str,
         content: &str,
         title: &str,
         headings: &str,
         highlighted: &str,
+        language: Language,
     ) {
         println!("inverted_index insert triggered => url : {url}");
-        let whole_content = format!("{} {} {} {}", title, headings, highlighted, content);
-        let words_map = whole_content.split_whitespace();
-        for word in words_map {
-            insert(word, url);
+        let fields = [
+            (Field::Url, url),
+            (Field::Site, url),
+            (Field::Title, title),
+            (Field::Headings, headings),
+            (Field::Highlighted, highlighted),
+            (Field::Content, content),
+        ];
+        let mut tokens = Vec::new();
+        for (field, document) in fields {
+            for (surface, word) in get_field_tokens(field, document, language) {
+                tokens.push((field, surface, word.to_lowercase()));
+            }
         }
+        segment::main::add_document(url, tokens);
     }
 
-    pub fn insert(text: &str, url: &str) {
-        let text = &text.to_string().to_lowercase();
-        let mut root_ref = root.write().unwrap();
-        if root_ref.is_none() {
-            *root_ref = Some(Node {
-                text: String::from(text),
-                urls: HashSet::from([String::from(url)]),
-                left: Box::new(Option::None),
-                right: Box::new(Option::None),
-            });
-            println!("root is updated");
-            return;
+    pub fn delete_document(url: &str) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        if url_index::main::delete(url)?.is_none() {
+            return Ok(false);
         }
-        insert_helper(&mut root_ref, text, url);
+        println!("inverted_index delete triggered => url : {url}");
+        segment::main::delete_document(url);
+        Ok(true)
     }
 
-    fn get_helper(node: &Option<Node>, text: &str) -> Option<Vec<String>> {
-        if node.is_none() {
-            return Option::None;
+    fn is_wildcard_match(pattern: &[char], text: &[char]) -> bool {
+        let (mut p_idx, mut t_idx) = (0, 0);
+        let mut backtrack: Option<(usize, usize)> = Option::None;
+        while t_idx < text.len() {
+            if p_idx < pattern.len() && (pattern[p_idx] == '?' || pattern[p_idx] == text[t_idx]) {
+                p_idx += 1;
+                t_idx += 1;
+            } else if p_idx < pattern.len() && pattern[p_idx] == '*' {
+                backtrack = Some((p_idx, t_idx));
+                p_idx += 1;
+            } else if let Some((star_idx, star_t_idx)) = backtrack {
+                p_idx = star_idx + 1;
+                t_idx = star_t_idx + 1;
+                backtrack = Some((star_idx, star_t_idx + 1));
+            } else {
+                return false;
+            }
         }
-        let node = node.as_ref().unwrap();
-        if node.text == text {
-            // return Some(node.urls_map.clone());
-            return Some(Vec::from_iter(node.urls.clone()));
-        } else if *node.text >= *text {
-            return get_helper(&node.right, text);
-        } else {
-            return get_helper(&node.left, text);
+        pattern[p_idx..].iter().all(|c| *c == '*')
+    }
+
+    pub fn get_surface_form(text: &str) -> String {
+        segment::main::get_snapshot().get_surface(text).unwrap_or(text.to_string())
+    }
+
+    pub fn get_doc_freq_by_text(text: &str, field: Option<Field>) -> usize {
+        segment::main::get_snapshot().get_doc_freq(text, field)
+    }
+
+    // returns dictionary terms matching a `*`/`?` pattern with their document frequency, most frequent first
+    pub fn expand_term(pattern: &str, field: Option<Field>, limit: usize) -> Vec<(String, usize)> {
+        let prefix = pattern
+            .split(['*', '?'])
+            .next()
+            .unwrap_or("")
+            .to_string();
+        let pattern = pattern.chars().collect::<Vec<char>>();
+        let mut doc_freqs: HashMap<String, usize> = HashMap::new();
+        segment::main::get_snapshot().visit_terms(&prefix, |term, reader, postings| {
+            if is_wildcard_match(&pattern, &term.chars().collect::<Vec<char>>()) {
+                *doc_freqs.entry(term.to_string()).or_insert(0) += reader.get_doc_freq(postings, field);
+            }
+        });
+        let mut result = doc_freqs
+            .into_iter()
+            .filter(|(_, doc_freq)| *doc_freq > 0)
+            .collect::<Vec<(String, usize)>>();
+        result.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        result.truncate(limit);
+        result
+    }
+
+    // returns (term, document frequency, edit distance) for dictionary terms close to text, closest first
+    pub fn fuzzy_expand_term(
+        text: &str,
+        field: Option<Field>,
+        limit: usize,
+    ) -> Vec<(String, usize, usize)> {
+        let max_distance = fuzzy::main::get_max_distance(text);
+        if max_distance == 0 {
+            return vec![];
         }
+        let automaton = LevenshteinAutomaton::new(text, max_distance);
+        let mut matches: HashMap<String, (usize, usize)> = HashMap::new();
+        segment::main::get_snapshot().visit_fuzzy_terms(&automaton, |term, reader, postings, distance| {
+            if distance > 0 {
+                matches.entry(term.to_string()).or_insert((0, distance)).0 += reader.get_doc_freq(postings, field);
+            }
+        });
+        let mut result = matches
+            .into_iter()
+            .filter(|(_, (doc_freq, _))| *doc_freq > 0)
+            .map(|(term, (doc_freq, distance))| (term, doc_freq, distance))
+            .collect::<Vec<(String, usize, usize)>>();
+        result.sort_by(|a, b| a.2.cmp(&b.2).then(b.1.cmp(&a.1)).then(a.0.cmp(&b.0)));
+        result.truncate(limit);
+        result
     }
 
+    fn get_max_expansions() -> usize {
+        env::var("MAX_TERM_EXPANSIONS")
+            .unwrap_or(String::from("50"))
+            .parse::<usize>()
+            .unwrap()
+    }
+
+    pub fn expand_query_terms(terms: &[QueryTerm], fuzzy: bool) -> Vec<QueryTerm> {
+        let max_expansions = get_max_expansions();
+        let mut expanded_terms = Vec::new();
+        for term in terms {
+            if term.kind != TermKind::Wildcard {
+                expanded_terms.push(term.clone());
+                if !fuzzy {
+                    continue;
+                }
+                let variants = fuzzy_expand_term(&term.text, term.field, max_expansions);
+                for (text, _, distance) in variants {
+                    expanded_terms.push(QueryTerm {
+                        text,
+                        weight: term.weight * fuzzy::main::get_fuzzy_weight(distance),
+                        ..term.clone()
+                    });
+                }
+                continue;
+            }
+            for (text, _) in expand_term(&term.text, term.field, max_expansions) {
+                expanded_terms.push(QueryTerm {
+                    text,
+                    kind: TermKind::Exact,
+                    ..term.clone()
+                });
+            }
+        }
+        expanded_terms
+    }
+
+    #[allow(dead_code)]
     pub fn get_by_text(text: &str) -> Option<Vec<String>> {
         let text = text.to_string().to_lowercase();
-        let root_ref = root.read().unwrap();
+        let snapshot = segment::main::get_snapshot();
         let mut combined_result = Vec::<String>::new();
         for word in text.split_whitespace() {
-            if let Some(mut word_result) = get_helper(&root_ref, word) {
-                // println!("word: {word}, map: {:?}", word_result);
-                combined_result.append(&mut word_result);
-            }
+            let urls = snapshot.get_postings(word, None).into_iter().filter_map(segment::main::get_url);
+            combined_result.extend(urls);
         }
-        // println!("hashmap result {:?}", combined_result);
         Some(combined_result)
     }
 
-    fn get_bm25_score(f_q_d: u64, d: u64, avdl: u64, n: u64, n_q: u64) -> f64 {
-        // f_q_d no of times query q occurs in doc
+    pub fn get_bm25_config() -> Bm25Config {
+        let get_param = |name: &str, default: f64| {
+            env::var(name)
+                .map(|value| value.parse::<f64>().unwrap())
+                .unwrap_or(default)
+        };
+        let k1 = get_param("BM25_K1", 1.2);
+        let b = get_param("BM25_B", 0.75);
+        let fields = Field::SCORED
+            .into_iter()
+            .map(|field| {
+                let name = format!("{:?}", field).to_uppercase();
+                let params = FieldParams {
+                    boost: get_param(&format!("FIELD_BOOST_{name}"), field.weight() as f64),
+                    b: get_param(&format!("BM25_B_{name}"), b),
+                };
+                (field, params)
+            })
+            .collect();
+        Bm25Config { k1, fields }
+    }
+
+    pub fn get_bm25f_explanation(
+        config: &Bm25Config,
+        field_stats: &[FieldStats],
+        n: u64,
+        n_q: u64,
+    ) -> Bm25fExplanation {
         // n_q no of docs containing query q
-        // d length of doc
-        // avdl average document len across collection
         // n total no of docs
-        // k how quickly the term frequency score saturates, inversely propotional to score saturation progress
-        // // high k -> query occurance of 5 times will result in 90% score
-        // // low k -> queyr occurance of 5 times will result in 70% score
-        // b contorls strength of the penalty for long documents
+        // every field's term frequency is length normalized on its own and boosted,
+        // the weighted sum is saturated once so a term repeated across fields can't outscore one strong match
+        // k1 how quickly the term frequency score saturates, inversely propotional to score saturation progress
+        // b contorls strength of the penalty for long fields, per field
         // idf give more weight to rare words than most repeated ones
+        let idf = get_idf(n, n_q);
+        let mut tf = 0.0;
+        let mut fields = Vec::new();
+        for stats in field_stats {
+            let params = &config.fields[&stats.field];
+            let avg_len = if stats.avg_len > 0.0 { stats.avg_len } else { 1.0 };
+            let norm = 1.0 - params.b + params.b * (stats.len as f64 / avg_len);
+            let weighted_tf = params.boost * stats.term_freq as f64 / norm;
+            tf += weighted_tf;
+            fields.push(FieldExplanation {
+                field: stats.field,
+                term_freq: stats.term_freq,
+                len: stats.len,
+                avg_len: stats.avg_len,
+                b: params.b,
+                boost: params.boost,
+                length_norm: norm,
+                weighted_tf,
+            });
+        }
+        let tf_satur = (tf * (config.k1 + 1.0)) / (tf + config.k1);
+        Bm25fExplanation {
+            score: idf * cmp::max(FloatOrd(tf_satur), FloatOrd(0.0)).0,
+            idf,
+            doc_freq: n_q,
+            total_count: n,
+            k1: config.k1,
+            tf,
+            fields,
+        }
+    }
 
-        let k = 1.2;
-        let b = 1 as f64;
+    pub fn get_idf(n: u64, n_q: u64) -> f64 {
         let n_q = n_q as f64;
-        let f_q_d = f_q_d as f64;
-        let d = d as f64;
-        let avdl = avdl as f64;
         let n = n as f64;
-        let idf = (((n - n_q + 0.5) / (n_q + 0.5)) + 1.0).ln();
-        let tf_satur = (f_q_d * (k + 1.0)) / (f_q_d + k * (1.0 - b + b * (d / avdl)));
-        let rank = cmp::max(FloatOrd(idf), FloatOrd(0.0)).0 * cmp::max(FloatOrd(tf_satur), FloatOrd(0.0)).0;
-        rank
-    }
-
-    fn get_score_helper(
-        weight: u8,
-        document: &String,
-        query: &str,
-        total_doc_len: u64,
-        total_doc_count: u64,
-        total_query_count_doc: u64,
-    ) -> f64 {
-        let doc_len = document.len() as u64;
-        let avg_content_len = total_doc_len / total_doc_count;
-        let mut occurance_count: u64 = 0;
-        for word in document.split_whitespace() {
-            if word == query {
-                occurance_count += 1
-            }
+        cmp::max(FloatOrd((((n - n_q + 0.5) / (n_q + 0.5)) + 1.0).ln()), FloatOrd(0.0)).0
+    }
+
+    // the saturated tf never reaches k1 + 1, so no document can score a term higher than this
+    pub fn get_max_bm25f_score(config: &Bm25Config, n: u64, n_q: u64) -> f64 {
+        get_idf(n, n_q) * (config.k1 + 1.0)
+    }
+
+    pub fn get_term_counts(field: Field, document: &str, language: Language) -> HashMap<String, u64> {
+        let mut term_counts = HashMap::new();
+        for word in get_field_words(field, document, language) {
+            *term_counts.entry(word).or_insert(0) += 1;
         }
-        let score = weight as f64
-            * get_bm25_score(
-                occurance_count,
-                doc_len,
-                avg_content_len,
-                total_doc_count,
-                total_query_count_doc,
-            );
-        score
+        term_counts
     }
 
-    fn get_text_by_scoring_helper(
-        text: &str,
-    ) -> Result<Vec<ResultScore>, Box<dyn Error>> {
-        let text = text.to_string().to_lowercase();
-        let combined_map = Arc::new(RwLock::new(HashMap::<String, (String, f64, f64)>::new()));
-        let mut all_handles = Vec::new();
-        for word in text.split_whitespace() {
-            let word = word.to_string();
-            let combined_map = Arc::clone(&combined_map);
-            let mut sub_handles = Vec::new();
-            let root_ref = Arc::clone(&root);
-            let handle = thread::spawn(move || {
-                let root_ref = root_ref.read().unwrap();
-                let word_urls = get_helper(&root_ref, &word);
-                if word_urls.is_none() {
-                    return;
-                }
-                let word_urls = word_urls.unwrap();
-                let word_freq = word_urls.len() as u64;
-                for word_url in word_urls {
-                    let word = word.to_string();
-                    let combined_map = Arc::clone(&combined_map);
-                    let sub_handle = thread::spawn(move || {
-                        let mut combined_map = combined_map.write().unwrap();
-                        let data_node = url_index::main::get_by_url(&word_url);
-                        if data_node.is_none() {
-                            return;
-                        }
-                        let data_node = data_node.unwrap();
-                        let url_index::Node {
-                            title,
-                            headings,
-                            highlighted,
-                            content,
-                            ..
-                        } = data_node;
-                        let curr_index_config = &url_index::INDEX_CONFIG;
-                        let curr_index_config = curr_index_config.read().unwrap();
-                        let url_score = get_score_helper(
-                            8,
-                            &word_url,
-                            &word,
-                            curr_index_config.field_count.url,
-                            curr_index_config.total_count,
-                            word_freq,
-                        );
-                        let title_score = get_score_helper(
-                            6,
-                            &title,
-                            &word,
-                            curr_index_config.field_count.title,
-                            curr_index_config.total_count,
-                            word_freq,
-                        );
-                        let headings_score = get_score_helper(
-                            4,
-                            &headings,
-                            &word,
-                            curr_index_config.field_count.headings,
-                            curr_index_config.total_count,
-                            word_freq,
-                        );
-                        let highlighted_score = get_score_helper(
-                            2,
-                            &highlighted,
-                            &word,
-                            curr_index_config.field_count.highlighted,
-                            curr_index_config.total_count,
-                            word_freq,
-                        );
-                        let content_score = get_score_helper(
-                            1,
-                            &content,
-                            &word,
-                            curr_index_config.field_count.content,
-                            curr_index_config.total_count,
-                            word_freq,
-                        );
-                        let curr_score = url_score + title_score + headings_score + highlighted_score + content_score;
-                        combined_map
-                            .entry(word_url.to_string())
-                            .and_modify(|entry| {
-                                entry.1 += curr_score;
-                                entry.2 += 1.0;
-                            })
-                            .or_insert((title.to_string(), curr_score, 1.0));
-                    });
-                    sub_handles.push(sub_handle);
-                }
-                for handle in sub_handles {
-                    let _ = handle.join();
-                }
-            });
-            all_handles.push(handle);
+    pub fn get_field_stats(
+        field: Field,
+        term_freq: u64,
+        field_lengths: &url_index::FieldCount,
+        corpus_stats: &CorpusStats,
+    ) -> FieldStats {
+        FieldStats {
+            field,
+            term_freq,
+            len: field_lengths.get(field),
+            avg_len: corpus_stats.get_avg_field_len(field),
+        }
+    }
+
+    pub fn get_site_docs(snapshot: &Snapshot, sites: &[String]) -> Option<HashSet<u32>> {
+        if sites.is_empty() {
+            return Option::None;
         }
-        for handle in all_handles {
-            let _ = handle.join();
+        let mut site_docs = HashSet::new();
+        for site in sites {
+            site_docs.extend(snapshot.get_postings(site, Some(Field::Site)));
         }
-        let combined_map = Arc::try_unwrap(combined_map).unwrap().into_inner().unwrap();
-        let final_result = combined_map
+        Some(site_docs)
+    }
+
+    pub fn get_language_boost() -> f64 {
+        env::var("PREFERRED_LANGUAGE_BOOST")
+            .unwrap_or(String::from("1.5"))
+            .parse::<f64>()
+            .unwrap()
+    }
+
+    pub fn get_text_by_scoring(text: &str, options: &SearchOptions) -> Result<SearchHits, Box<dyn Error>> {
+        let query = query::main::parse_with_options(text, options);
+        let hits = executor::main::execute(&query, options, options.offset.saturating_add(options.limit));
+        let results = hits
+            .results
             .into_iter()
-            .map(|(url, (title, score, freq))| ResultScore {
-                score: score * freq, // boosting score for pages which has entire search text
-                url: url.to_string(),
-                title: title.to_string(),
+            .skip(options.offset)
+            .map(|result| ResultScore {
+                snippets: snippet::main::get_snippets_by_url(&result.url, &hits.terms),
+                ..result
             })
-            .collect::<Vec<ResultScore>>();
-        Ok(final_result)
-    }
-
-    pub fn get_text_by_scoring(text: &str) -> Result<Vec<ResultScore>, Box<dyn Error>> {
-        let top_k: u8 = env::var("TOP_K_RESULTS")
-            .unwrap_or(String::from("10"))
-            .parse::<u8>()
-            .unwrap();
-        let result = get_text_by_scoring_helper(text)?;
-        let top_results = get_top_k_filterd(result, top_k)?;
-        Ok(top_results)
-    }
-
-    fn get_top_k_filterd(
-        url_results: Vec<ResultScore>,
-        top_k: u8,
-    ) -> Result<Vec<ResultScore>, Box<dyn Error>> {
-        let mut heap = BinaryHeap::new();
-        // println!("raw result 🥺🥺: {:#?}", url_results);
-        for ResultScore { url, title, score } in url_results.iter() {
-            heap.push((FloatOrd(-score), url, title));
-            if heap.len() as u64 > top_k as u64 {
-                heap.pop();
+            .collect();
+        Ok(SearchHits {
+            results,
+            total_hits: hits.total_hits,
+            terms: hits.terms,
+            facets: hits.facets,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // (url, title, content)
+    const DOCS: [(&str, &str, &str); 6] = [
+        (
+            "https://docs.example.com/rust/ownership",
+            "Rust ownership explained",
+            "Ownership is how rust manages memory without a garbage collector.",
+        ),
+        (
+            "https://blog.example.com/posts/2021/weekly-notes-on-many-different-topics",
+            "Weekly notes",
+            "This week we talked about cooking, travel, gardening and briefly rust. \
+             Next week more cooking, more travel and more gardening.",
+        ),
+        (
+            "https://docs.example.com/python/memory",
+            "Python memory management",
+            "Python manages memory with reference counting and a garbage collector.",
+        ),
+        (
+            "https://forum.example.com/t/rust-rust-rust",
+            "rust rust rust",
+            "rust rust rust rust rust rust rust rust",
+        ),
+        (
+            "https://docs.example.com/gardening/tomatoes",
+            "Growing tomatoes",
+            "Tomatoes need sun, water and a garden with good soil. Gardening tips for tomatoes.",
+        ),
+        (
+            "https://news.example.com/memory-prices",
+            "Memory prices fall",
+            "Prices of memory chips fall again this quarter, memory makers say.",
+        ),
+    ];
+
+    // (query, [(doc index, graded relevance)])
+    const JUDGMENTS: [(&str, &[(usize, f64)]); 4] = [
+        ("rust ownership", &[(0, 3.0), (3, 1.0), (1, 0.0)]),
+        ("memory garbage collector", &[(2, 3.0), (0, 2.0), (5, 1.0)]),
+        ("gardening tomatoes", &[(4, 3.0), (1, 1.0)]),
+        ("rust memory", &[(0, 3.0), (2, 1.0), (3, 1.0), (5, 1.0)]),
+    ];
+
+    fn get_document(doc: (&str, &str, &str), field: Field) -> String {
+        match field {
+            Field::Url | Field::Site => doc.0.to_string(),
+            Field::Title => doc.1.to_string(),
+            Field::Content => doc.2.to_string(),
+            Field::Headings | Field::Highlighted => String::new(),
+        }
+    }
+
+    fn get_lengths(doc: (&str, &str, &str), byte_len: bool) -> url_index::FieldCount {
+        let get_len = |field: Field| {
+            let document = get_document(doc, field);
+            if byte_len {
+                document.len() as u64
+            } else {
+                main::get_field_words(field, &document, Language::English).len() as u64
             }
+        };
+        url_index::FieldCount {
+            url: get_len(Field::Url),
+            title: get_len(Field::Title),
+            headings: get_len(Field::Headings),
+            highlighted: get_len(Field::Highlighted),
+            content: get_len(Field::Content),
+        }
+    }
+
+    fn get_index_config(byte_len: bool) -> CorpusStats {
+        let mut field_count = url_index::FieldCount::default();
+        for doc in DOCS {
+            let lengths = get_lengths(doc, byte_len);
+            field_count.url += lengths.url;
+            field_count.title += lengths.title;
+            field_count.headings += lengths.headings;
+            field_count.highlighted += lengths.highlighted;
+            field_count.content += lengths.content;
         }
-        // println!("heap 🥺🥺 {top_k} : {:#?}", heap);
-        let final_result = heap
-            .into_sorted_vec()
+        CorpusStats {
+            total_count: DOCS.len() as u64,
+            field_count,
+            terms: HashMap::new(),
+        }
+    }
+
+    fn get_term_freq(doc: (&str, &str, &str), field: Field, term: &str) -> u64 {
+        main::get_field_words(field, &get_document(doc, field), Language::English)
+            .into_iter()
+            .filter(|word| word == term)
+            .count() as u64
+    }
+
+    fn get_doc_freq(term: &str) -> u64 {
+        DOCS.iter()
+            .filter(|doc| Field::SCORED.iter().any(|field| get_term_freq(**doc, *field, term) > 0))
+            .count() as u64
+    }
+
+    // the scoring before BM25F: byte lengths, b = 1, integer averages and
+    // every field saturated on its own before the fixed weights are summed
+    fn get_legacy_score(doc: (&str, &str, &str), term: &str, config: &CorpusStats) -> f64 {
+        let (k, b) = (1.2, 1.0);
+        let n = config.total_count as f64;
+        let n_q = get_doc_freq(term) as f64;
+        let idf = (((n - n_q + 0.5) / (n_q + 0.5)) + 1.0).ln();
+        let lengths = get_lengths(doc, true);
+        let mut score = 0.0;
+        for field in Field::SCORED {
+            let f_q_d = get_term_freq(doc, field, term) as f64;
+            let d = lengths.get(field) as f64;
+            let avdl = (config.field_count.get(field) / config.total_count) as f64;
+            let tf_satur = (f_q_d * (k + 1.0)) / (f_q_d + k * (1.0 - b + b * (d / avdl)));
+            score += field.weight() as f64 * idf.max(0.0) * tf_satur.max(0.0);
+        }
+        score
+    }
+
+    fn get_bm25f_score(doc: (&str, &str, &str), term: &str, config: &CorpusStats) -> f64 {
+        let bm25_config = Bm25Config {
+            k1: 1.2,
+            fields: Field::SCORED
+                .into_iter()
+                .map(|field| (field, FieldParams { boost: field.weight() as f64, b: 0.75 }))
+                .collect(),
+        };
+        let lengths = get_lengths(doc, false);
+        let field_stats = Field::SCORED
             .into_iter()
-            .map(|(score, url, title)| ResultScore {
-                score: -score.0,
-                url: url.to_string(),
-                title: title.to_string(),
+            .map(|field| {
+                main::get_field_stats(field, get_term_freq(doc, field, term), &lengths, config)
             })
-            .collect::<Vec<ResultScore>>();
-        Ok(final_result)
+            .collect::<Vec<FieldStats>>();
+        main::get_bm25f_explanation(&bm25_config, &field_stats, config.total_count, get_doc_freq(term)).score
+    }
+
+    fn get_ndcg(ranking: &[usize], judgments: &[(usize, f64)]) -> f64 {
+        let get_grade =
+            |doc: usize| judgments.iter().find(|(index, _)| *index == doc).map_or(0.0, |(_, grade)| *grade);
+        let get_dcg = |grades: Vec<f64>| {
+            grades
+                .into_iter()
+                .enumerate()
+                .map(|(rank, grade)| (2_f64.powf(grade) - 1.0) / ((rank + 2) as f64).log2())
+                .sum::<f64>()
+        };
+        let mut ideal = judgments.iter().map(|(_, grade)| *grade).collect::<Vec<f64>>();
+        ideal.sort_by(|a, b| b.total_cmp(a));
+        get_dcg(ranking.iter().map(|doc| get_grade(*doc)).collect()) / get_dcg(ideal)
+    }
+
+    fn get_mean_ndcg(score_fn: fn((&str, &str, &str), &str, &CorpusStats) -> f64, byte_len: bool) -> f64 {
+        let config = get_index_config(byte_len);
+        let mut total = 0.0;
+        for (text, judgments) in JUDGMENTS {
+            let terms = analyzer::main::analyze_query(text, Language::English);
+            let mut scores = DOCS
+                .iter()
+                .enumerate()
+                .map(|(index, doc)| {
+                    let score = terms.iter().map(|term| score_fn(*doc, term, &config)).sum::<f64>();
+                    (index, score)
+                })
+                .filter(|(_, score)| *score > 0.0)
+                .collect::<Vec<(usize, f64)>>();
+            scores.sort_by(|a, b| b.1.total_cmp(&a.1));
+            let ranking = scores.into_iter().map(|(index, _)| index).collect::<Vec<usize>>();
+            total += get_ndcg(&ranking, judgments);
+        }
+        total / JUDGMENTS.len() as f64
+    }
+
+    #[test]
+    fn bm25f_improves_ndcg_over_legacy_scoring() {
+        let legacy = get_mean_ndcg(get_legacy_score, true);
+        let bm25f = get_mean_ndcg(get_bm25f_score, false);
+        assert!(bm25f > legacy, "legacy ndcg {legacy:.3}, bm25f ndcg {bm25f:.3}");
+    }
+
+    #[test]
+    fn bm25f_score_is_zero_without_matches_and_empty_averages() {
+        let config = Bm25Config {
+            k1: 1.2,
+            fields: HashMap::from([(Field::Title, FieldParams { boost: 6.0, b: 0.75 })]),
+        };
+        let stats = FieldStats {
+            field: Field::Title,
+            term_freq: 0,
+            len: 3,
+            avg_len: 0.0,
+        };
+        assert_eq!(main::get_bm25f_explanation(&config, &[stats], 0, 0).score, 0.0);
+        let stats = FieldStats {
+            field: Field::Title,
+            term_freq: 1,
+            len: 3,
+            avg_len: 0.0,
+        };
+        assert!(main::get_bm25f_explanation(&config, &[stats], 1, 1).score.is_finite());
+    }
+
+    #[test]
+    fn url_terms_are_stemmed_like_query_terms() {
+        let url_words = main::get_field_words(Field::Url, "https://docs.example.com/guides/Installation", Language::English);
+        assert_eq!(url_words, vec!["doc", "exampl", "com", "guid", "instal"]);
+        for term in crate::analyzer::main::analyze_query("docs installation", Language::English) {
+            assert!(url_words.contains(&term));
+        }
+    }
+
+    #[test]
+    fn exact_terms_expand_to_fuzzy_variants_and_wildcards_to_dictionary_terms() {
+        let _lock = url_index::TEST_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+        let url = "https://fuzzy-test.example/";
+        url_index::main::insert(url, "quetzalcoatl quetzal", "", "", "", Language::English, Option::None, "").unwrap();
+        main::insert_by_content(url, "quetzalcoatl quetzal", "", "", "", Language::English);
+        segment::main::flush();
+
+        let query = query::main::parse("quetzalcoatk quetz*", &[Language::English]);
+        let get_terms = |fuzzy: bool| {
+            main::expand_query_terms(&query.terms, fuzzy)
+                .into_iter()
+                .map(|term| (term.text, term.kind, term.group, term.weight))
+                .collect::<Vec<(String, TermKind, usize, f64)>>()
+        };
+        let terms = get_terms(true);
+        assert!(terms.contains(&(String::from("quetzalcoatk"), TermKind::Exact, 0, 1.0)));
+        assert!(terms.contains(&(String::from("quetzalcoatl"), TermKind::Exact, 0, 0.5)));
+        assert!(terms.contains(&(String::from("quetzal"), TermKind::Exact, 1, 1.0)));
+        assert!(terms.contains(&(String::from("quetzalcoatl"), TermKind::Exact, 1, 1.0)));
+        let terms = get_terms(false);
+        assert!(!terms.iter().any(|(text, _, group, _)| text == "quetzalcoatl" && *group == 0));
+        // short words aren't corrected
+        assert!(main::fuzzy_expand_term("qz", Option::None, 10).is_empty());
+
+        assert!(main::delete_document(url).unwrap());
+    }
+
+    #[test]
+    fn single_cjk_characters_find_the_runs_they_are_part_of() {
+        let _lock = url_index::TEST_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+        let url = "https://cjk-test.example/";
+        url_index::main::insert(url, "黒猫が好き", "", "", "", Language::Unknown, Option::None, "").unwrap();
+        main::insert_by_content(url, "黒猫が好き", "", "", "", Language::Unknown);
+        segment::main::flush();
+
+        let options = SearchOptions {
+            fuzzy: false,
+            ..Default::default()
+        };
+        for text in ["猫", "好", "黒猫", "猫が"] {
+            let query = query::main::parse(text, &[Language::Unknown]);
+            let urls = executor::main::execute(&query, &options, 10)
+                .results
+                .into_iter()
+                .map(|result| result.url)
+                .collect::<Vec<String>>();
+            assert_eq!(urls, vec![url], "{text}");
+        }
+
+        assert!(main::delete_document(url).unwrap());
+    }
+
+    #[test]
+    fn rebuilt_stats_match_incremental_stats() {
+        let _lock = url_index::TEST_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+        for (url, title, content) in DOCS {
+            url_index::main::insert(url, content, title, "", "", Language::English, Option::None, "").unwrap();
+            main::insert_by_content(url, content, title, "", "", Language::English);
+        }
+        segment::main::flush();
+        let get_stats = || {
+            let corpus_stats = stats::CORPUS_STATS.read().unwrap();
+            let mut terms = corpus_stats
+                .terms
+                .iter()
+                .map(|(term, term_stats)| {
+                    let mut field_doc_freq = term_stats.field_doc_freq.iter().map(|(field, count)| (format!("{field:?}"), *count)).collect::<Vec<(String, u64)>>();
+                    field_doc_freq.sort();
+                    (term.to_string(), term_stats.doc_freq, field_doc_freq)
+                })
+                .collect::<Vec<(String, u64, Vec<(String, u64)>)>>();
+            terms.sort();
+            (corpus_stats.total_count, format!("{:?}", corpus_stats.field_count), terms)
+        };
+        let incremental = get_stats();
+        url_index::main::rebuild_stats();
+        assert_eq!(get_stats(), incremental);
+
+        for (url, _, _) in DOCS {
+            assert!(main::delete_document(url).unwrap());
+        }
     }
 }
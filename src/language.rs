@@ -0,0 +1,271 @@
+use lazy_static::lazy_static;
+use rust_stemmers::{Algorithm, Stemmer};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::env;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Language {
+    English,
+    French,
+    German,
+    Spanish,
+    Italian,
+    Portuguese,
+    Dutch,
+    Swedish,
+    Russian,
+    Danish,
+    Norwegian,
+    Finnish,
+    Turkish,
+    Greek,
+    Arabic,
+    Polish,
+    Czech,
+    Ukrainian,
+    Hebrew,
+    Persian,
+    Hindi,
+    Indonesian,
+    Vietnamese,
+    Thai,
+    Chinese,
+    Japanese,
+    Korean,
+    #[default]
+    Unknown,
+}
+
+lazy_static! {
+    static ref STOP_WORDS: HashMap<Language, HashSet<&'static str>> = HashMap::from([
+        (Language::English, main::get_word_set(include_str!("../data/stopwords/en.txt"))),
+        (Language::French, main::get_word_set(include_str!("../data/stopwords/fr.txt"))),
+        (Language::German, main::get_word_set(include_str!("../data/stopwords/de.txt"))),
+        (Language::Spanish, main::get_word_set(include_str!("../data/stopwords/es.txt"))),
+        (Language::Italian, main::get_word_set(include_str!("../data/stopwords/it.txt"))),
+        (Language::Portuguese, main::get_word_set(include_str!("../data/stopwords/pt.txt"))),
+        (Language::Dutch, main::get_word_set(include_str!("../data/stopwords/nl.txt"))),
+        (Language::Swedish, main::get_word_set(include_str!("../data/stopwords/sv.txt"))),
+        (Language::Russian, main::get_word_set(include_str!("../data/stopwords/ru.txt"))),
+    ]);
+}
+
+impl Language {
+    pub const ALL: [Language; 27] = [
+        Language::English,
+        Language::French,
+        Language::German,
+        Language::Spanish,
+        Language::Italian,
+        Language::Portuguese,
+        Language::Dutch,
+        Language::Swedish,
+        Language::Russian,
+        Language::Danish,
+        Language::Norwegian,
+        Language::Finnish,
+        Language::Turkish,
+        Language::Greek,
+        Language::Arabic,
+        Language::Polish,
+        Language::Czech,
+        Language::Ukrainian,
+        Language::Hebrew,
+        Language::Persian,
+        Language::Hindi,
+        Language::Indonesian,
+        Language::Vietnamese,
+        Language::Thai,
+        Language::Chinese,
+        Language::Japanese,
+        Language::Korean,
+    ];
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            Language::English => "en",
+            Language::French => "fr",
+            Language::German => "de",
+            Language::Spanish => "es",
+            Language::Italian => "it",
+            Language::Portuguese => "pt",
+            Language::Dutch => "nl",
+            Language::Swedish => "sv",
+            Language::Russian => "ru",
+            Language::Danish => "da",
+            Language::Norwegian => "no",
+            Language::Finnish => "fi",
+            Language::Turkish => "tr",
+            Language::Greek => "el",
+            Language::Arabic => "ar",
+            Language::Polish => "pl",
+            Language::Czech => "cs",
+            Language::Ukrainian => "uk",
+            Language::Hebrew => "he",
+            Language::Persian => "fa",
+            Language::Hindi => "hi",
+            Language::Indonesian => "id",
+            Language::Vietnamese => "vi",
+            Language::Thai => "th",
+            Language::Chinese => "zh",
+            Language::Japanese => "ja",
+            Language::Korean => "ko",
+            Language::Unknown => "",
+        }
+    }
+
+    // accepts "en", "en-US" and "en_gb" style tags
+    pub fn from_code(code: &str) -> Language {
+        let code = code.trim().to_lowercase();
+        let code = match code.split(['-', '_']).next().unwrap_or("") {
+            "nb" | "nn" => "no",
+            "iw" => "he",
+            "in" => "id",
+            code => code,
+        };
+        Language::ALL
+            .into_iter()
+            .find(|language| language.code() == code)
+            .unwrap_or(Language::Unknown)
+    }
+
+    fn from_whatlang(lang: whatlang::Lang) -> Language {
+        match lang {
+            whatlang::Lang::Eng => Language::English,
+            whatlang::Lang::Fra => Language::French,
+            whatlang::Lang::Deu => Language::German,
+            whatlang::Lang::Spa => Language::Spanish,
+            whatlang::Lang::Ita => Language::Italian,
+            whatlang::Lang::Por => Language::Portuguese,
+            whatlang::Lang::Nld => Language::Dutch,
+            whatlang::Lang::Swe => Language::Swedish,
+            whatlang::Lang::Rus => Language::Russian,
+            whatlang::Lang::Dan => Language::Danish,
+            whatlang::Lang::Nob => Language::Norwegian,
+            whatlang::Lang::Fin => Language::Finnish,
+            whatlang::Lang::Tur => Language::Turkish,
+            whatlang::Lang::Ell => Language::Greek,
+            whatlang::Lang::Ara => Language::Arabic,
+            whatlang::Lang::Pol => Language::Polish,
+            whatlang::Lang::Ces => Language::Czech,
+            whatlang::Lang::Ukr => Language::Ukrainian,
+            whatlang::Lang::Heb => Language::Hebrew,
+            whatlang::Lang::Pes => Language::Persian,
+            whatlang::Lang::Hin => Language::Hindi,
+            whatlang::Lang::Ind => Language::Indonesian,
+            whatlang::Lang::Vie => Language::Vietnamese,
+            whatlang::Lang::Tha => Language::Thai,
+            whatlang::Lang::Cmn => Language::Chinese,
+            whatlang::Lang::Jpn => Language::Japanese,
+            whatlang::Lang::Kor => Language::Korean,
+            _ => Language::Unknown,
+        }
+    }
+
+    pub fn get_stemmer(&self) -> Option<Stemmer> {
+        let algorithm = match self {
+            Language::English => Algorithm::English,
+            Language::French => Algorithm::French,
+            Language::German => Algorithm::German,
+            Language::Spanish => Algorithm::Spanish,
+            Language::Italian => Algorithm::Italian,
+            Language::Portuguese => Algorithm::Portuguese,
+            Language::Dutch => Algorithm::Dutch,
+            Language::Swedish => Algorithm::Swedish,
+            Language::Russian => Algorithm::Russian,
+            Language::Danish => Algorithm::Danish,
+            Language::Norwegian => Algorithm::Norwegian,
+            Language::Finnish => Algorithm::Finnish,
+            Language::Turkish => Algorithm::Turkish,
+            Language::Greek => Algorithm::Greek,
+            Language::Arabic => Algorithm::Arabic,
+            _ => return Option::None,
+        };
+        Some(Stemmer::create(algorithm))
+    }
+
+    pub fn is_stop_word(&self, word: &str) -> bool {
+        STOP_WORDS
+            .get(self)
+            .is_some_and(|stop_words| stop_words.contains(word))
+    }
+}
+
+pub mod main {
+    use super::*;
+
+    pub fn get_word_set(words: &'static str) -> HashSet<&'static str> {
+        words
+            .lines()
+            .map(|word| word.trim())
+            .filter(|word| !word.is_empty())
+            .collect()
+    }
+
+    pub fn get_default_language() -> Language {
+        let code = env::var("DEFAULT_LANGUAGE").unwrap_or(String::from("en"));
+        Language::from_code(&code)
+    }
+
+    // trigram classifier on the text, unreliable guesses on short text fall back to the default language
+    pub fn detect(text: &str) -> Language {
+        let sample = text.chars().take(2000).collect::<String>();
+        match whatlang::detect(&sample) {
+            Some(info) if info.is_reliable() => Language::from_whatlang(info.lang()),
+            _ => get_default_language(),
+        }
+    }
+
+    // declared languages win over the classifier, `<html lang>` is the most specific of them
+    pub fn detect_document(html_lang: Option<&str>, content_language: Option<&str>, text: &str) -> Language {
+        let declared = [html_lang, content_language.and_then(|value| value.split(',').next())];
+        for code in declared.into_iter().flatten() {
+            let language = Language::from_code(code);
+            if language != Language::Unknown {
+                return language;
+            }
+        }
+        detect(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENGLISH: &str = "The quick brown fox jumps over the lazy dog while the farmer watches \
+                           from the porch and wonders whether the weather will hold until evening.";
+    const FRENCH: &str = "Le renard brun rapide saute par-dessus le chien paresseux pendant que le \
+                          fermier regarde depuis le porche et se demande si le temps tiendra.";
+
+    #[test]
+    fn codes_drop_region_subtags_and_map_aliases() {
+        assert_eq!(Language::from_code("en"), Language::English);
+        assert_eq!(Language::from_code(" EN-us "), Language::English);
+        assert_eq!(Language::from_code("pt_BR"), Language::Portuguese);
+        assert_eq!(Language::from_code("nb-NO"), Language::Norwegian);
+        assert_eq!(Language::from_code("iw"), Language::Hebrew);
+        assert_eq!(Language::from_code("xx-YY"), Language::Unknown);
+        assert_eq!(Language::from_code(""), Language::Unknown);
+    }
+
+    #[test]
+    fn text_is_classified_and_short_text_falls_back_to_the_default() {
+        assert_eq!(main::detect(ENGLISH), Language::English);
+        assert_eq!(main::detect(FRENCH), Language::French);
+        assert_eq!(main::detect("ok"), main::get_default_language());
+        assert_eq!(main::detect(""), main::get_default_language());
+    }
+
+    #[test]
+    fn html_lang_wins_over_content_language_and_both_over_the_text() {
+        assert_eq!(main::detect_document(Some("fr-CA"), Some("de"), ENGLISH), Language::French);
+        assert_eq!(main::detect_document(Option::None, Some("de-DE, en"), ENGLISH), Language::German);
+        assert_eq!(main::detect_document(Option::None, Option::None, FRENCH), Language::French);
+        // unknown declarations are skipped
+        assert_eq!(main::detect_document(Some("xx"), Some("de"), ENGLISH), Language::German);
+        assert_eq!(main::detect_document(Some("xx"), Some(""), FRENCH), Language::French);
+        assert_eq!(main::detect_document(Option::None, Option::None, "ok"), main::get_default_language());
+    }
+}
@@ -1,23 +1,46 @@
 use axum::{
     Router,
-    extract::{Json, Path},
+    extract::{Json, Path, Query},
+    http::{HeaderMap, StatusCode},
     response::Html,
     routing,
 };
-use dotenv;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::time::Duration;
 use std::{env, error::Error, fs, thread};
-use tokio;
 
+mod analyzer;
 mod crawler;
+mod diversity;
+mod executor;
+mod facets;
+mod fuzzy;
+mod index_file;
 mod inverted_index;
+mod language;
+mod postings;
+mod query;
+mod recency;
+mod segment;
+mod snippet;
+mod spelling;
+mod stats;
+mod synonyms;
 mod url_index;
+mod wal;
 
 #[derive(Serialize, Deserialize)]
 struct ApiRespSearch {
     msg: String,
     data: Vec<inverted_index::ResultScore>,
+    suggestion: Option<String>,
+    total_hits: usize,
+    offset: usize,
+    limit: usize,
+    // offset of the following page, none on the last one
+    next_offset: Option<usize>,
+    facets: facets::Facets,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -25,6 +48,146 @@ struct ApiRespIndex {
     msg: String,
 }
 
+#[derive(Serialize, Deserialize)]
+struct Suggestion {
+    text: String,
+    doc_freq: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Suggestions {
+    terms: Vec<Suggestion>,
+    titles: Vec<Suggestion>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ApiRespSuggest {
+    msg: String,
+    data: Suggestions,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SuggestParams {
+    q: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ApiRespReload {
+    msg: String,
+    count: usize,
+}
+
+#[derive(Serialize)]
+struct ApiRespStats {
+    msg: String,
+    data: stats::StatsSummary,
+}
+
+// query string form of SearchBody, lists are comma separated and boosts are `field:boost`
+#[derive(Serialize, Deserialize)]
+struct SearchParams {
+    q: Option<String>,
+    fuzzy: Option<bool>,
+    lang: Option<String>,
+    prefer_lang: Option<String>,
+    page: Option<usize>,
+    offset: Option<usize>,
+    limit: Option<usize>,
+    site: Option<String>,
+    after: Option<String>,
+    before: Option<String>,
+    mime_type: Option<String>,
+    sort: Option<String>,
+    boosts: Option<String>,
+    explain: Option<bool>,
+    recency: Option<bool>,
+    per_host: Option<usize>,
+    diversify: Option<bool>,
+    date_interval: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct SearchFilters {
+    #[serde(default)]
+    site: Vec<String>,
+    lang: Option<String>,
+    after: Option<String>,
+    before: Option<String>,
+    #[serde(default)]
+    mime_type: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SearchBody {
+    q: String,
+    #[serde(default)]
+    filters: SearchFilters,
+    fuzzy: Option<bool>,
+    prefer_lang: Option<String>,
+    sort: Option<String>,
+    page: Option<usize>,
+    offset: Option<usize>,
+    limit: Option<usize>,
+    #[serde(default)]
+    boosts: HashMap<String, f64>,
+    explain: Option<bool>,
+    recency: Option<bool>,
+    per_host: Option<usize>,
+    diversify: Option<bool>,
+    date_interval: Option<String>,
+}
+
+impl SearchParams {
+    fn into_body(self, q: String) -> Result<SearchBody, String> {
+        let mut boosts = HashMap::new();
+        for boost in self.boosts.iter().flat_map(|boosts| boosts.split(',')) {
+            let (name, value) = boost
+                .split_once(':')
+                .ok_or(format!("invalid boost {boost}, expected field:boost"))?;
+            let value = value
+                .trim()
+                .parse::<f64>()
+                .map_err(|_| format!("invalid boost {boost}, expected field:boost"))?;
+            boosts.insert(name.trim().to_string(), value);
+        }
+        let get_list = |value: &Option<String>| {
+            value
+                .iter()
+                .flat_map(|value| value.split(','))
+                .map(|value| value.trim().to_string())
+                .filter(|value| !value.is_empty())
+                .collect::<Vec<String>>()
+        };
+        Ok(SearchBody {
+            q,
+            filters: SearchFilters {
+                site: get_list(&self.site),
+                lang: self.lang,
+                after: self.after,
+                before: self.before,
+                mime_type: get_list(&self.mime_type),
+            },
+            fuzzy: self.fuzzy,
+            prefer_lang: self.prefer_lang,
+            sort: self.sort,
+            page: self.page,
+            offset: self.offset,
+            limit: self.limit,
+            boosts,
+            explain: self.explain,
+            recency: self.recency,
+            per_host: self.per_host,
+            diversify: self.diversify,
+            date_interval: self.date_interval,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct DocumentParams {
+    url: String,
+}
+
 #[derive(Serialize, Deserialize)]
 struct IndexPayload {
     url: String,
@@ -38,37 +201,292 @@ async fn crawl_index_url(Json(payload): Json<IndexPayload>) -> Json<ApiRespIndex
         msg: "pages indexing finished".to_string(),
     })
 }
-#[axum::debug_handler]
-async fn get_pages_by_search_text(Path(search_text): Path<String>) -> Json<ApiRespSearch> {
-    let url_resp = inverted_index::main::get_text_by_scoring(&search_text);
+fn get_search_options(body: &SearchBody) -> Result<query::SearchOptions, String> {
+    let (offset, limit) = query::main::get_page_window(body.page, body.offset, body.limit)?;
+    let get_date = |value: &Option<String>, parse: fn(&str) -> Option<chrono::DateTime<chrono::Utc>>| match value {
+        Some(value) => parse(value)
+            .map(Some)
+            .ok_or(format!("invalid date {value}, expected YYYY-MM-DD or RFC 3339")),
+        None => Ok(Option::None),
+    };
+    let sort = match &body.sort {
+        Some(sort) => query::SortOrder::from_name(sort).ok_or(format!("invalid sort {sort}"))?,
+        None => query::SortOrder::Relevance,
+    };
+    let date_interval = match &body.date_interval {
+        Some(interval) => {
+            facets::DateInterval::from_name(interval).ok_or(format!("invalid date interval {interval}"))?
+        }
+        None => facets::DateInterval::Month,
+    };
+    let mut field_boosts = HashMap::new();
+    for (name, boost) in body.boosts.iter() {
+        let field = query::main::get_field(name)
+            .filter(|field| inverted_index::Field::SCORED.contains(field))
+            .ok_or(format!("invalid boost field {name}"))?;
+        if !boost.is_finite() || *boost < 0.0 {
+            return Err(format!("invalid boost {boost} for {name}"));
+        }
+        field_boosts.insert(field, *boost);
+    }
+    Ok(query::SearchOptions {
+        fuzzy: body.fuzzy.unwrap_or(true),
+        language: body.filters.lang.as_deref().map(language::Language::from_code),
+        preferred_language: body
+            .prefer_lang
+            .as_deref()
+            .map(language::Language::from_code),
+        offset,
+        limit,
+        sites: body.filters.site.clone(),
+        after: get_date(&body.filters.after, recency::main::parse_date)?,
+        // inclusive, `before=2024-03-05` keeps pages of that day
+        before: get_date(&body.filters.before, recency::main::parse_end_date)?,
+        mime_types: body.filters.mime_type.clone(),
+        sort,
+        field_boosts,
+        explain: body.explain.unwrap_or(false),
+        recency: body.recency.unwrap_or(true),
+        per_host: body.per_host.unwrap_or_else(diversity::main::get_max_per_host),
+        diversify: body.diversify.unwrap_or(false),
+        date_interval,
+    })
+}
+
+fn get_error_resp(msg: String) -> (StatusCode, Json<ApiRespSearch>) {
+    let data = ApiRespSearch {
+        msg,
+        data: vec![],
+        suggestion: Option::None,
+        total_hits: 0,
+        offset: 0,
+        limit: 0,
+        next_offset: Option::None,
+        facets: facets::Facets::default(),
+    };
+    (StatusCode::BAD_REQUEST, Json(data))
+}
+
+async fn search(body: Result<SearchBody, String>) -> (StatusCode, Json<ApiRespSearch>) {
+    let body = match body {
+        Ok(body) => body,
+        Err(err) => return get_error_resp(err),
+    };
+    let options = match get_search_options(&body) {
+        Ok(options) => options,
+        Err(err) => return get_error_resp(err),
+    };
+    let search_text = body.q;
+    let (offset, limit) = (options.offset, options.limit);
+    // scoring and spelling walk the dictionary, they run on the blocking pool instead of an
+    // async worker
+    let scoring_text = search_text.clone();
+    let (url_resp, suggestion) = tokio::task::spawn_blocking(move || {
+        let url_resp =
+            inverted_index::main::get_text_by_scoring(&scoring_text, &options).map_err(|err| err.to_string());
+        let suggestion = match &url_resp {
+            Ok(hits) if !spelling::main::is_weak(hits.total_hits) => Option::None,
+            _ => spelling::main::get_suggestion(&scoring_text, &query::main::get_languages(&options)),
+        };
+        (url_resp, suggestion)
+    })
+    .await
+    .unwrap_or_else(|err| (Err(err.to_string()), Option::None));
     if let Err(err) = url_resp {
         println!("search text error => text: {search_text}, error: {:?}", err);
         let data = ApiRespSearch {
             msg: "No Pages Found!".to_string(),
             data: vec![],
+            suggestion,
+            total_hits: 0,
+            offset,
+            limit,
+            next_offset: Option::None,
+            facets: facets::Facets::default(),
         };
-        return Json(data);
+        return (StatusCode::OK, Json(data));
     }
-    let url_resp = url_resp.unwrap();
+    let inverted_index::SearchHits {
+        results,
+        total_hits,
+        facets,
+        ..
+    } = url_resp.unwrap();
     println!(
-        "search text resp => text: {search_text}, result: {:#?}",
-        url_resp
+        "search text resp => text: {search_text}, total hits: {total_hits}, result: {:#?}",
+        results
     );
-    return Json(ApiRespSearch {
+    let next_offset = match offset + results.len() < total_hits && !results.is_empty() {
+        true => Some(offset + results.len()),
+        false => Option::None,
+    };
+    let data = ApiRespSearch {
         msg: "Data Fetched successfully".to_string(),
-        data: url_resp,
-    });
+        data: results,
+        suggestion,
+        total_hits,
+        offset,
+        limit,
+        next_offset,
+        facets,
+    };
+    (StatusCode::OK, Json(data))
+}
+
+// kept for old clients, the text in the path can't hold a `/`
+#[axum::debug_handler]
+async fn get_pages_by_search_text(
+    Path(search_text): Path<String>,
+    Query(params): Query<SearchParams>,
+) -> (StatusCode, Json<ApiRespSearch>) {
+    search(params.into_body(search_text)).await
+}
+
+#[axum::debug_handler]
+async fn get_search(Query(mut params): Query<SearchParams>) -> (StatusCode, Json<ApiRespSearch>) {
+    let Some(search_text) = params.q.take() else {
+        return get_error_resp("missing query parameter q".to_string());
+    };
+    search(params.into_body(search_text)).await
+}
+
+#[axum::debug_handler]
+async fn post_search(Json(body): Json<SearchBody>) -> (StatusCode, Json<ApiRespSearch>) {
+    search(Ok(body)).await
+}
+
+#[axum::debug_handler]
+async fn get_suggestions(Query(params): Query<SuggestParams>) -> Json<ApiRespSuggest> {
+    let limit = env::var("SUGGEST_RESULTS")
+        .unwrap_or(String::from("8"))
+        .parse::<usize>()
+        .unwrap();
+    let text = params.q.to_lowercase();
+    let mut terms = Vec::new();
+    // only the word being typed is completed, the words before it are kept as they are
+    if !text.is_empty() && !text.ends_with(char::is_whitespace) {
+        let (head, prefix) = text.rsplit_once(char::is_whitespace).unwrap_or(("", &text));
+        let prefix = analyzer::main::normalize(prefix).replace(['*', '?'], "");
+        let pattern = format!("{}*", prefix);
+        for (term, doc_freq) in inverted_index::main::expand_term(&pattern, None, limit) {
+            let term = inverted_index::main::get_surface_form(&term);
+            let text = format!("{} {}", head, term).trim().to_string();
+            terms.push(Suggestion { text, doc_freq });
+        }
+    }
+    let titles = match text.trim().is_empty() {
+        true => vec![],
+        false => url_index::main::get_title_completions(text.trim(), limit)
+            .into_iter()
+            .map(|(text, doc_freq)| Suggestion { text, doc_freq })
+            .collect(),
+    };
+    Json(ApiRespSuggest {
+        msg: "Data Fetched successfully".to_string(),
+        data: Suggestions { terms, titles },
+    })
+}
+
+#[axum::debug_handler]
+async fn get_stats() -> Json<ApiRespStats> {
+    Json(ApiRespStats {
+        msg: "Data Fetched successfully".to_string(),
+        data: stats::main::get_summary(),
+    })
+}
+
+// the x-admin-token header has to match ADMIN_TOKEN, admin routes are closed while it is unset
+fn is_admin(headers: &HeaderMap) -> bool {
+    let admin_token = env::var("ADMIN_TOKEN").unwrap_or_default();
+    if admin_token.is_empty() {
+        return false;
+    }
+    headers
+        .get("x-admin-token")
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value == admin_token)
+}
+
+#[axum::debug_handler]
+async fn reload_synonyms(headers: HeaderMap) -> (StatusCode, Json<ApiRespReload>) {
+    if !is_admin(&headers) {
+        let data = ApiRespReload {
+            msg: "Unauthorized".to_string(),
+            count: 0,
+        };
+        return (StatusCode::UNAUTHORIZED, Json(data));
+    }
+    match synonyms::main::load() {
+        Ok(count) => (
+            StatusCode::OK,
+            Json(ApiRespReload {
+                msg: "synonyms reloaded".to_string(),
+                count,
+            }),
+        ),
+        Err(err) => {
+            println!("synonyms reload error : {:?}", err);
+            let data = ApiRespReload {
+                msg: format!("synonyms reload failed: {err}"),
+                count: 0,
+            };
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(data))
+        }
+    }
+}
+
+#[axum::debug_handler]
+async fn delete_document(
+    headers: HeaderMap,
+    Query(params): Query<DocumentParams>,
+) -> (StatusCode, Json<ApiRespIndex>) {
+    if !is_admin(&headers) {
+        let data = ApiRespIndex {
+            msg: "Unauthorized".to_string(),
+        };
+        return (StatusCode::UNAUTHORIZED, Json(data));
+    }
+    match inverted_index::main::delete_document(&params.url) {
+        Ok(true) => (),
+        Ok(false) => {
+            let data = ApiRespIndex {
+                msg: "Page Not Found!".to_string(),
+            };
+            return (StatusCode::NOT_FOUND, Json(data));
+        }
+        Err(err) => {
+            println!("err while deleting page : {:?}", err);
+            let data = ApiRespIndex {
+                msg: "Page could not be deleted".to_string(),
+            };
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(data));
+        }
+    }
+    (
+        StatusCode::OK,
+        Json(ApiRespIndex {
+            msg: "page deleted successfully".to_string(),
+        }),
+    )
 }
 
 fn init() -> Result<(), Box<dyn Error>> {
     dotenv::dotenv().ok();
+    if let Err(err) = synonyms::main::load() {
+        println!("err while loading synonyms : {:?}", err);
+    }
     let tcp_thread = thread::spawn(|| {
         let app = Router::new()
             .route(
                 "/api/search/{search_text}",
                 routing::get(get_pages_by_search_text),
             )
+            .route("/api/search", routing::get(get_search).post(post_search))
+            .route("/api/suggest", routing::get(get_suggestions))
+            .route("/api/stats", routing::get(get_stats))
             .route("/api/index", routing::post(crawl_index_url))
+            .route("/api/documents", routing::delete(delete_document))
+            .route("/api/admin/synonyms/reload", routing::post(reload_synonyms))
             .route("/", routing::get(get_homepage));
         let runtime = tokio::runtime::Runtime::new().unwrap();
         runtime.block_on(async {
@@ -76,14 +494,16 @@ fn init() -> Result<(), Box<dyn Error>> {
             axum::serve(listener, app).await.unwrap()
         });
     });
-    let inverted_index_thread = thread::spawn(|| {
-        let _ = inverted_index::main::index();
-    });
-    let url_index_thread = thread::spawn(|| {
+    let index_thread = thread::spawn(|| {
+        // the saved inverted index is checked against the loaded documents, so it goes second
         let _ = url_index::main::index();
+        if let Err(err) = inverted_index::main::index() {
+            println!("err while loading inverted index : {:?}", err);
+        }
     });
-    let _ = inverted_index_thread.join().unwrap();
-    let _ = url_index_thread.join().unwrap();
+    index_thread.join().unwrap();
+    segment::main::start_background();
+    wal::main::start_sync();
     let index_save_interval = env::var("INDEX_SAVE_INTERVAL_MIN")
         .unwrap_or(String::from("30"))
         .parse::<u16>()
@@ -92,7 +512,12 @@ fn init() -> Result<(), Box<dyn Error>> {
         let _ = thread::spawn(move || {
             loop {
                 thread::sleep(Duration::from_secs(index_save_interval as u64 * 60));
-                let _ = url_index::main::write_to_file();
+                if let Err(err) = url_index::main::write_to_file() {
+                    println!("err while writing index : {:?}", err);
+                }
+                if let Err(err) = inverted_index::main::write_to_file() {
+                    println!("err while writing inverted index : {:?}", err);
+                }
             }
         });
     }
@@ -100,7 +525,7 @@ fn init() -> Result<(), Box<dyn Error>> {
         .unwrap_or("false".to_string())
         .parse::<bool>()
         .unwrap();
-    if stop_crawler != true {
+    if !stop_crawler {
         thread::spawn(|| {
             loop {
                 let _ = crawler::main::init_multiple();
@@ -172,7 +597,7 @@ async fn get_homepage() -> Html<String> {
     }
     let file_data = file_data.unwrap();
     let api_base_url = env::var("API_BASE_URL").unwrap_or("http://localhost:8080".to_string());
-    let total_pages_count = url_index::INDEX_CONFIG.read().unwrap().total_count;
+    let total_pages_count = stats::main::get_total_count();
     let file_data = file_data.replace("__API_BASE_URL__", &api_base_url).replace("__CRAWLED_COUNT__", &total_pages_count.to_string());
     Html(file_data)
 }
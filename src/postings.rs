@@ -0,0 +1,373 @@
+
+// doc ids per block, a lookup decodes at most one block
+pub const BLOCK_LEN: usize = 128;
+
+// last doc id of a block and where its data and frequencies start
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Skip {
+    last: u32,
+    offset: u32,
+    freq_offset: u32,
+}
+
+// ascending doc ids stored as gaps to the previous id. full blocks are bitpacked at the width
+// of their largest gap, the last partial block is varint encoded. skip entries let a lookup
+// go straight to the one block that can hold a doc id
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PostingList {
+    len: u32,
+    skips: Vec<Skip>,
+    data: Vec<u8>,
+    // occurrences of the term in each document, every block bitpacked at its own width so
+    // one is read without decoding the others. empty for lists of documents only
+    freqs: Vec<u8>,
+}
+
+impl PostingList {
+    pub fn from_sorted(doc_ids: &[u32]) -> PostingList {
+        PostingList::from_sorted_with_freqs(doc_ids, &[])
+    }
+
+    // freqs is empty or has one entry per doc id
+    pub fn from_sorted_with_freqs(doc_ids: &[u32], freqs: &[u32]) -> PostingList {
+        let mut skips = Vec::with_capacity(doc_ids.len().div_ceil(BLOCK_LEN));
+        let mut data = Vec::new();
+        let mut freq_data = Vec::new();
+        let mut prev = 0;
+        for (idx, block) in doc_ids.chunks(BLOCK_LEN).enumerate() {
+            skips.push(Skip {
+                last: *block.last().unwrap(),
+                offset: data.len() as u32,
+                freq_offset: freq_data.len() as u32,
+            });
+            if !freqs.is_empty() {
+                main::pack(&freqs[idx * BLOCK_LEN..idx * BLOCK_LEN + block.len()], &mut freq_data);
+            }
+            let gaps = block
+                .iter()
+                .map(|doc_id| {
+                    let gap = doc_id - prev;
+                    prev = *doc_id;
+                    gap
+                })
+                .collect::<Vec<u32>>();
+            match block.len() == BLOCK_LEN {
+                true => main::pack(&gaps, &mut data),
+                false => gaps.iter().for_each(|gap| main::write_varint(*gap, &mut data)),
+            }
+        }
+        data.shrink_to_fit();
+        freq_data.shrink_to_fit();
+        PostingList {
+            len: doc_ids.len() as u32,
+            skips,
+            data,
+            freqs: freq_data,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn get_block(&self, block: usize) -> Vec<u32> {
+        let len = (self.len() - block * BLOCK_LEN).min(BLOCK_LEN);
+        let data = &self.data[self.skips[block].offset as usize..];
+        let mut gaps = Vec::with_capacity(len);
+        match len == BLOCK_LEN {
+            true => main::unpack(data, len, &mut gaps),
+            false => {
+                let mut pos = 0;
+                for _ in 0..len {
+                    gaps.push(main::read_varint(data, &mut pos));
+                }
+            }
+        }
+        let mut doc_id = match block {
+            0 => 0,
+            _ => self.skips[block - 1].last,
+        };
+        for gap in gaps.iter_mut() {
+            doc_id += *gap;
+            *gap = doc_id;
+        }
+        gaps
+    }
+
+    pub fn contains(&self, doc_id: u32) -> bool {
+        let block = self.skips.partition_point(|skip| skip.last < doc_id);
+        block < self.skips.len() && self.get_block(block).binary_search(&doc_id).is_ok()
+    }
+
+    // 0 when the document isn't in the list, 1 when it is and no frequencies are kept
+    pub fn get_freq(&self, doc_id: u32) -> u32 {
+        let block = self.skips.partition_point(|skip| skip.last < doc_id);
+        if block == self.skips.len() {
+            return 0;
+        }
+        let Ok(pos) = self.get_block(block).binary_search(&doc_id) else {
+            return 0;
+        };
+        match self.freqs.is_empty() {
+            true => 1,
+            false => main::get_packed(&self.freqs[self.skips[block].freq_offset as usize..], pos),
+        }
+    }
+
+    // every frequency in doc id order
+    pub fn get_freqs(&self) -> Vec<u32> {
+        let mut freqs = Vec::with_capacity(self.len());
+        for (block, skip) in self.skips.iter().enumerate() {
+            let len = (self.len() - block * BLOCK_LEN).min(BLOCK_LEN);
+            match self.freqs.is_empty() {
+                true => freqs.extend(std::iter::repeat_n(1, len)),
+                false => main::unpack(&self.freqs[skip.freq_offset as usize..], len, &mut freqs),
+            }
+        }
+        freqs
+    }
+
+    pub fn iter(&self) -> PostingIter<'_> {
+        PostingIter {
+            list: self,
+            block: 0,
+            doc_ids: Vec::new(),
+            pos: 0,
+        }
+    }
+
+    // the encoded gaps and frequencies, what the index file stores
+    pub fn get_bytes(&self) -> (&[u8], &[u8]) {
+        (&self.data, &self.freqs)
+    }
+
+    #[cfg(test)]
+    pub fn get_heap_size(&self) -> usize {
+        self.skips.capacity() * std::mem::size_of::<Skip>() + self.data.capacity() + self.freqs.capacity()
+    }
+}
+
+// decodes one block at a time
+pub struct PostingIter<'a> {
+    list: &'a PostingList,
+    block: usize,
+    doc_ids: Vec<u32>,
+    pos: usize,
+}
+
+impl Iterator for PostingIter<'_> {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<u32> {
+        if self.pos == self.doc_ids.len() {
+            if self.block == self.list.skips.len() {
+                return Option::None;
+            }
+            self.doc_ids = self.list.get_block(self.block);
+            self.block += 1;
+            self.pos = 0;
+        }
+        self.pos += 1;
+        Some(self.doc_ids[self.pos - 1])
+    }
+}
+
+pub mod main {
+    use super::*;
+
+    pub fn write_varint(mut value: u32, out: &mut Vec<u8>) {
+        while value >= 0x80 {
+            out.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        out.push(value as u8);
+    }
+
+    pub fn read_varint(data: &[u8], pos: &mut usize) -> u32 {
+        let mut value = 0;
+        let mut shift = 0;
+        loop {
+            let byte = data[*pos];
+            *pos += 1;
+            value |= ((byte & 0x7f) as u32) << shift;
+            if byte < 0x80 {
+                return value;
+            }
+            shift += 7;
+        }
+    }
+
+    // a width byte, then every value in that many bits, lowest bits first
+    pub fn pack(values: &[u32], out: &mut Vec<u8>) {
+        let width = values.iter().map(|value| 32 - value.leading_zeros()).max().unwrap_or(0);
+        out.push(width as u8);
+        let mut acc = 0u64;
+        let mut bits = 0;
+        for value in values {
+            acc |= (*value as u64) << bits;
+            bits += width;
+            while bits >= 8 {
+                out.push(acc as u8);
+                acc >>= 8;
+                bits -= 8;
+            }
+        }
+        if bits > 0 {
+            out.push(acc as u8);
+        }
+    }
+
+    // the value at idx of a packed run, without unpacking the ones before it
+    pub fn get_packed(data: &[u8], idx: usize) -> u32 {
+        let width = data[0] as usize;
+        let bit = idx * width;
+        let mut acc = 0u64;
+        for (shift, byte) in data[1 + bit / 8..].iter().take((bit % 8 + width).div_ceil(8)).enumerate() {
+            acc |= (*byte as u64) << (shift * 8);
+        }
+        ((acc >> (bit % 8)) & ((1u64 << width) - 1)) as u32
+    }
+
+    // bytes of a packed run of len values starting at pos, none when it runs past data
+    fn get_packed_len(data: &[u8], pos: usize, len: usize) -> Option<usize> {
+        let width = *data.get(pos)? as usize;
+        let packed_len = 1 + (len * width).div_ceil(8);
+        (width <= 32 && pos + packed_len <= data.len()).then_some(packed_len)
+    }
+
+    fn read_varint_checked(data: &[u8], pos: &mut usize) -> Option<u32> {
+        let mut value = 0u32;
+        for shift in (0..35).step_by(7) {
+            let byte = *data.get(*pos)?;
+            *pos += 1;
+            value |= ((byte & 0x7f) as u32).checked_shl(shift)?;
+            if byte < 0x80 {
+                return Some(value);
+            }
+        }
+        Option::None
+    }
+
+    // (doc id, frequency) pairs of len documents from the bytes get_bytes returned. unlike
+    // reading a built list every length and id is checked, the bytes may come from a
+    // damaged file
+    pub fn decode(len: usize, data: &[u8], freqs: &[u8]) -> Option<Vec<(u32, u32)>> {
+        let mut docs = Vec::with_capacity(len.min(data.len() * 8));
+        let (mut pos, mut freq_pos) = (0, 0);
+        let mut prev = Option::None;
+        for start in (0..len).step_by(BLOCK_LEN) {
+            let block_len = (len - start).min(BLOCK_LEN);
+            let mut gaps = Vec::with_capacity(block_len);
+            if block_len == BLOCK_LEN {
+                let packed_len = get_packed_len(data, pos, block_len)?;
+                unpack(&data[pos..], block_len, &mut gaps);
+                pos += packed_len;
+            } else {
+                for _ in 0..block_len {
+                    gaps.push(read_varint_checked(data, &mut pos)?);
+                }
+            }
+            let packed_len = get_packed_len(freqs, freq_pos, block_len)?;
+            let mut block_freqs = Vec::with_capacity(block_len);
+            unpack(&freqs[freq_pos..], block_len, &mut block_freqs);
+            freq_pos += packed_len;
+            for (gap, freq) in gaps.into_iter().zip(block_freqs) {
+                // ids ascend, only the first one can repeat the starting 0
+                let doc_id = match prev {
+                    Some(_) if gap == 0 => return Option::None,
+                    Some(prev) => u32::checked_add(prev, gap)?,
+                    None => gap,
+                };
+                prev = Some(doc_id);
+                docs.push((doc_id, freq));
+            }
+        }
+        (pos == data.len() && freq_pos == freqs.len()).then_some(docs)
+    }
+
+    pub fn unpack(data: &[u8], len: usize, out: &mut Vec<u32>) {
+        let width = data[0] as u32;
+        let mask = (1u64 << width) - 1;
+        let mut acc = 0u64;
+        let mut bits = 0;
+        let mut pos = 1;
+        for _ in 0..len {
+            while bits < width {
+                acc |= (data[pos] as u64) << bits;
+                pos += 1;
+                bits += 8;
+            }
+            out.push((acc & mask) as u32);
+            acc >>= width;
+            bits -= width;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn posting_lists_round_trip_and_skip_to_blocks() {
+        for doc_ids in [vec![], vec![0], vec![7, 9, 4_000_000_000], (0..1000).map(|id| id * 3).collect::<Vec<u32>>()] {
+            let list = PostingList::from_sorted(&doc_ids);
+            assert_eq!(list.len(), doc_ids.len());
+            assert_eq!(list.iter().collect::<Vec<u32>>(), doc_ids);
+            for doc_id in doc_ids.iter() {
+                assert!(list.contains(*doc_id));
+                assert!(!list.contains(doc_id + 1) || doc_ids.contains(&(doc_id + 1)));
+            }
+        }
+        let list = PostingList::from_sorted(&(0..1000).map(|id| id * 3).collect::<Vec<u32>>());
+        assert!(!list.contains(3000));
+        // 7 full blocks of 2 bit gaps and a varint tail of 104 one byte gaps
+        assert_eq!(list.data.len(), 7 * (1 + 128 * 2 / 8) + 104);
+        assert_eq!(list.get_freq(3), 1);
+        assert_eq!(list.get_freq(4), 0);
+    }
+
+    #[test]
+    fn encoded_lists_decode_and_damaged_ones_are_rejected() {
+        let doc_ids = (0..300).map(|id| id * 5 + 1).collect::<Vec<u32>>();
+        let freqs = (0..300).map(|idx| idx % 3 + 1).collect::<Vec<u32>>();
+        let list = PostingList::from_sorted_with_freqs(&doc_ids, &freqs);
+        let (data, freq_data) = list.get_bytes();
+        let expected = doc_ids.into_iter().zip(freqs).collect::<Vec<(u32, u32)>>();
+        assert_eq!(main::decode(300, data, freq_data), Some(expected));
+        assert_eq!(main::decode(0, &[], &[]), Some(vec![]));
+
+        assert_eq!(main::decode(301, data, freq_data), Option::None);
+        assert_eq!(main::decode(299, data, freq_data), Option::None);
+        assert_eq!(main::decode(300, &data[..data.len() - 1], freq_data), Option::None);
+        assert_eq!(main::decode(300, data, &freq_data[..freq_data.len() - 1]), Option::None);
+        // a width above 32 bits
+        let mut wide = data.to_vec();
+        wide[0] = 40;
+        assert_eq!(main::decode(300, &wide, freq_data), Option::None);
+        // a repeated doc id
+        let list = PostingList::from_sorted_with_freqs(&[3, 3], &[1, 1]);
+        let (data, freq_data) = list.get_bytes();
+        assert_eq!(main::decode(2, data, freq_data), Option::None);
+    }
+
+    #[test]
+    fn frequencies_are_read_by_doc_id() {
+        let doc_ids = (0..300).map(|id| id * 2).collect::<Vec<u32>>();
+        // mostly small counts with a few wide ones
+        let freqs = (0..300)
+            .map(|idx| if idx % 7 == 0 { 70_000 } else { idx % 7 })
+            .collect::<Vec<u32>>();
+        let list = PostingList::from_sorted_with_freqs(&doc_ids, &freqs);
+        assert_eq!(list.iter().collect::<Vec<u32>>(), doc_ids);
+        assert_eq!(list.get_freqs(), freqs);
+        for (doc_id, freq) in doc_ids.iter().zip(freqs.iter()) {
+            assert_eq!(list.get_freq(*doc_id), *freq);
+            assert_eq!(list.get_freq(doc_id + 1), 0);
+        }
+    }
+}
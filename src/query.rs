@@ -0,0 +1,359 @@
+use crate::analyzer;
+use crate::diversity;
+use crate::facets::DateInterval;
+use crate::inverted_index::{self, Field};
+use crate::language::{self, Language};
+use crate::synonyms;
+use crate::url_index;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::env;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TermKind {
+    Exact,
+    // `foo*` and `f?o`, expanded through the term dictionary before scoring
+    Wildcard,
+}
+
+#[derive(Clone, Debug)]
+pub struct QueryTerm {
+    pub text: String,
+    pub field: Option<Field>,
+    pub kind: TermKind,
+    // position of the query word this term came from, expansions of one word share it
+    pub group: usize,
+    pub weight: f64,
+}
+
+#[derive(Debug, Default)]
+pub struct ParsedQuery {
+    pub terms: Vec<QueryTerm>,
+    pub sites: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortOrder {
+    Relevance,
+    // newest first, score only breaks ties
+    Date,
+}
+
+impl SortOrder {
+    pub fn from_name(name: &str) -> Option<SortOrder> {
+        match name.trim() {
+            "relevance" | "score" => Some(SortOrder::Relevance),
+            "date" => Some(SortOrder::Date),
+            _ => Option::None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SearchOptions {
+    // typo tolerant expansion of exact terms, can be turned off per query
+    pub fuzzy: bool,
+    // only documents in this language are returned
+    pub language: Option<Language>,
+    // documents in this language are boosted
+    pub preferred_language: Option<Language>,
+    // results skipped before the returned page
+    pub offset: usize,
+    pub limit: usize,
+    // same as `site:` in the query text
+    pub sites: Vec<String>,
+    // documents dated within the range, both ends inclusive
+    pub after: Option<DateTime<Utc>>,
+    pub before: Option<DateTime<Utc>>,
+    // Content-Type of the page without parameters, any of them matches
+    pub mime_types: Vec<String>,
+    pub sort: SortOrder,
+    // replaces the configured BM25F boost of a field for this query
+    pub field_boosts: HashMap<Field, f64>,
+    // attach the score breakdown to every result
+    pub explain: bool,
+    // boost recently published pages, configured through RECENCY_*
+    pub recency: bool,
+    // at most this many results of one host, 0 turns collapsing off
+    pub per_host: usize,
+    // reorder the top results by maximal marginal relevance
+    pub diversify: bool,
+    // bucket size of the date facet
+    pub date_interval: DateInterval,
+}
+
+impl Default for SearchOptions {
+    fn default() -> SearchOptions {
+        SearchOptions {
+            fuzzy: true,
+            language: Option::None,
+            preferred_language: Option::None,
+            offset: 0,
+            limit: main::get_default_limit(),
+            sites: vec![],
+            after: Option::None,
+            before: Option::None,
+            mime_types: vec![],
+            sort: SortOrder::Relevance,
+            field_boosts: HashMap::new(),
+            explain: false,
+            recency: true,
+            per_host: diversity::main::get_max_per_host(),
+            diversify: false,
+            date_interval: DateInterval::Month,
+        }
+    }
+}
+
+impl QueryTerm {
+    pub fn new(text: &str, field: Option<Field>, group: usize) -> QueryTerm {
+        let kind = match text.contains(['*', '?']) {
+            true => TermKind::Wildcard,
+            false => TermKind::Exact,
+        };
+        QueryTerm {
+            text: text.to_string(),
+            field,
+            kind,
+            group,
+            weight: 1.0,
+        }
+    }
+}
+
+pub mod main {
+    use super::*;
+
+    pub fn get_default_limit() -> usize {
+        env::var("TOP_K_RESULTS")
+            .unwrap_or(String::from("10"))
+            .parse::<usize>()
+            .unwrap()
+    }
+
+    fn get_max_limit() -> usize {
+        env::var("SEARCH_MAX_LIMIT")
+            .unwrap_or(String::from("100"))
+            .parse::<usize>()
+            .unwrap()
+    }
+
+    // deepest result a client can page to, top-k has to score offset + limit documents
+    fn get_max_window() -> usize {
+        env::var("SEARCH_MAX_WINDOW")
+            .unwrap_or(String::from("1000"))
+            .parse::<usize>()
+            .unwrap()
+    }
+
+    // page is 1-based, an explicit offset wins over it. returns (offset, limit) within the caps,
+    // a window starting past the deepest result is an error so nothing is scored for it
+    pub fn get_page_window(
+        page: Option<usize>,
+        offset: Option<usize>,
+        limit: Option<usize>,
+    ) -> Result<(usize, usize), String> {
+        let limit = limit.unwrap_or_else(get_default_limit).min(get_max_limit());
+        let offset = match (offset, page) {
+            (Some(offset), _) => offset,
+            (Option::None, Some(page)) => page.saturating_sub(1).saturating_mul(limit),
+            (Option::None, Option::None) => 0,
+        };
+        let max_window = get_max_window();
+        if offset >= max_window {
+            return Err(format!("invalid offset {offset}, results are only available up to {max_window}"));
+        }
+        Ok((offset, limit.min(max_window - offset)))
+    }
+
+    pub fn get_site(value: &str) -> Option<String> {
+        get_value_words(Field::Site, &value.to_lowercase(), &[Language::Unknown])
+            .into_iter()
+            .next()
+    }
+
+    pub fn get_field(name: &str) -> Option<Field> {
+        match name {
+            "url" | "inurl" => Some(Field::Url),
+            "title" | "intitle" => Some(Field::Title),
+            "heading" | "headings" => Some(Field::Headings),
+            "highlighted" => Some(Field::Highlighted),
+            "content" | "intext" => Some(Field::Content),
+            "site" => Some(Field::Site),
+            _ => Option::None,
+        }
+    }
+
+    // the terms of a value in every candidate language, once each
+    fn get_value_words(field: Field, value: &str, languages: &[Language]) -> Vec<String> {
+        let mut words: Vec<String> = Vec::new();
+        for language in languages {
+            let language_words = match field {
+                Field::Url | Field::Site => inverted_index::main::get_field_words(field, value, *language),
+                _ => analyzer::main::analyze_query(value, *language),
+            };
+            for word in language_words {
+                if !words.contains(&word) {
+                    words.push(word);
+                }
+            }
+        }
+        words
+    }
+
+    pub fn parse(text: &str, languages: &[Language]) -> ParsedQuery {
+        let mut query = ParsedQuery::default();
+        let mut plain_words = Vec::new();
+        for (group, word) in text.to_lowercase().split_whitespace().enumerate() {
+            let scoped = word
+                .split_once(':')
+                .and_then(|(name, value)| get_field(name).map(|field| (field, value)))
+                .filter(|(_, value)| !value.is_empty());
+            match scoped {
+                Some((Field::Site, value)) => {
+                    if let Some(site) = get_site(value) {
+                        query.sites.push(site);
+                    }
+                }
+                Some((field, value)) if value.contains(['*', '?']) => {
+                    let value = analyzer::main::normalize(value);
+                    query.terms.push(QueryTerm::new(&value, Some(field), group));
+                }
+                Some((field, value)) => {
+                    for text in get_value_words(field, value, languages) {
+                        query.terms.push(QueryTerm::new(&text, Some(field), group));
+                    }
+                }
+                None if word.contains(['*', '?']) => {
+                    let word = analyzer::main::normalize(word);
+                    query.terms.push(QueryTerm::new(&word, Option::None, group));
+                }
+                None => {
+                    plain_words.push((group, synonyms::main::normalize_phrase(word)));
+                    for text in get_value_words(Field::Content, word, languages) {
+                        query.terms.push(QueryTerm::new(&text, Option::None, group));
+                    }
+                }
+            }
+        }
+        add_synonyms(&mut query, &plain_words, languages);
+        query
+    }
+
+    // synonyms share the group of the first word they replace and score lower than the typed words
+    fn add_synonyms(query: &mut ParsedQuery, plain_words: &[(usize, String)], languages: &[Language]) {
+        let words = plain_words
+            .iter()
+            .map(|(_, word)| word.to_string())
+            .collect::<Vec<String>>();
+        let weight = synonyms::main::get_weight();
+        for synonym_match in synonyms::main::get_matches(&words) {
+            let group = plain_words[synonym_match.start].0;
+            for phrase in synonym_match.phrases {
+                for text in get_value_words(Field::Content, &phrase, languages) {
+                    let mut term = QueryTerm::new(&text, Option::None, group);
+                    term.weight = weight;
+                    query.terms.push(term);
+                }
+            }
+        }
+    }
+
+    // a few words are too little to detect a language from, without one from the request the
+    // query is analyzed in every language of the indexed documents
+    pub fn get_languages(options: &SearchOptions) -> Vec<Language> {
+        let languages = match options.language.or(options.preferred_language) {
+            Some(language) => vec![language],
+            None => url_index::main::get_languages(),
+        };
+        match languages.is_empty() {
+            true => vec![language::main::get_default_language()],
+            false => languages,
+        }
+    }
+
+    // the query text plus the filters passed next to it
+    pub fn parse_with_options(text: &str, options: &SearchOptions) -> ParsedQuery {
+        let mut query = parse(text, &get_languages(options));
+        query.sites.extend(options.sites.iter().filter_map(|site| get_site(site)));
+        query
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn page_window_is_capped_and_rejects_offsets_past_it() {
+        assert_eq!(main::get_page_window(Option::None, Option::None, Option::None), Ok((0, 10)));
+        assert_eq!(main::get_page_window(Some(3), Option::None, Some(20)), Ok((40, 20)));
+        assert_eq!(main::get_page_window(Some(3), Some(5), Some(20)), Ok((5, 20)));
+        assert_eq!(main::get_page_window(Option::None, Some(990), Some(20)), Ok((990, 10)));
+        assert_eq!(main::get_page_window(Option::None, Option::None, Some(1000)), Ok((0, 100)));
+        assert!(main::get_page_window(Option::None, Some(1000), Option::None).is_err());
+        assert!(main::get_page_window(Option::None, Some(usize::MAX), Option::None).is_err());
+        assert!(main::get_page_window(Some(usize::MAX), Option::None, Some(100)).is_err());
+    }
+
+    fn get_terms(query: &ParsedQuery) -> Vec<(&str, Option<Field>, usize)> {
+        query.terms.iter().map(|term| (term.text.as_str(), term.field, term.group)).collect()
+    }
+
+    #[test]
+    fn words_are_analyzed_in_every_candidate_language() {
+        let query = main::parse("chevaux running", &[Language::English, Language::French]);
+        let terms = get_terms(&query);
+        assert!(terms.contains(&("cheval", Option::None, 0)));
+        assert!(terms.contains(&("run", Option::None, 1)));
+        // stop words aren't indexed, a query of only them has nothing to match
+        assert!(main::parse("the of", &[Language::English]).terms.is_empty());
+    }
+
+    #[test]
+    fn site_filters_keep_top_level_domains_and_single_label_hosts() {
+        assert_eq!(main::parse("site:com rust", &[Language::English]).sites, vec!["com"]);
+        assert_eq!(main::parse("site:localhost", &[Language::English]).sites, vec!["localhost"]);
+        assert_eq!(main::parse("site:www.Docs.Example.com:8080", &[Language::English]).sites, vec!["docs.example.com"]);
+        let sites = inverted_index::main::get_field_words(Field::Site, "https://docs.example.com/a", Language::Unknown);
+        assert_eq!(sites, vec!["docs.example.com", "example.com", "com"]);
+        let sites = inverted_index::main::get_field_words(Field::Site, "http://localhost:3000/", Language::Unknown);
+        assert_eq!(sites, vec!["localhost"]);
+    }
+
+    #[test]
+    fn field_scopes_apply_to_their_value_only() {
+        let query = main::parse("intitle:Rust heading:guides inurl:docs memory foo:bar", &[Language::English]);
+        assert_eq!(
+            get_terms(&query),
+            vec![
+                ("rust", Some(Field::Title), 0),
+                ("guid", Some(Field::Headings), 1),
+                ("doc", Some(Field::Url), 2),
+                ("memori", Option::None, 3),
+                // not a field, the word boundaries keep the colon between letters
+                ("foo:bar", Option::None, 4),
+            ]
+        );
+        // an empty value is a plain word
+        assert_eq!(get_terms(&main::parse("title:", &[Language::English])), vec![("titl", Option::None, 0)]);
+    }
+
+    #[test]
+    fn wildcards_are_normalized_but_not_stemmed() {
+        let query = main::parse("Rü* title:Runn?ng plain", &[Language::English]);
+        let terms = query
+            .terms
+            .iter()
+            .map(|term| (term.text.as_str(), term.field, term.kind))
+            .collect::<Vec<(&str, Option<Field>, TermKind)>>();
+        assert_eq!(
+            terms,
+            vec![
+                ("ru*", Option::None, TermKind::Wildcard),
+                ("runn?ng", Some(Field::Title), TermKind::Wildcard),
+                ("plain", Option::None, TermKind::Exact),
+            ]
+        );
+    }
+}
@@ -0,0 +1,145 @@
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use lazy_static::lazy_static;
+use std::env;
+
+// gaussian decay on document age, the same shape as elasticsearch's `gauss` function score
+#[derive(Clone, Debug)]
+pub struct DecayConfig {
+    // the boost of a brand new document is 1 + weight, 0 turns recency off
+    pub weight: f64,
+    // ages below offset count as brand new
+    pub offset_days: f64,
+    // at offset + scale the decay has dropped to `decay`
+    pub scale_days: f64,
+    pub decay: f64,
+}
+
+lazy_static! {
+    pub static ref DECAY_CONFIG: DecayConfig = main::get_decay_config();
+}
+
+impl DecayConfig {
+    pub fn get_max_boost(&self) -> f64 {
+        1.0 + self.weight.max(0.0)
+    }
+
+    // 1 for documents younger than offset, `decay` at offset + scale and towards 0 after it.
+    // a date in the future counts as age 0, it can't make a page fresher than brand new
+    pub fn get_decay(&self, age_days: f64) -> f64 {
+        if self.scale_days <= 0.0 || self.decay <= 0.0 || self.decay >= 1.0 {
+            return 0.0;
+        }
+        let sigma_sq = -self.scale_days.powi(2) / (2.0 * self.decay.ln());
+        let distance = (age_days.max(0.0) - self.offset_days).max(0.0);
+        (-distance.powi(2) / (2.0 * sigma_sq)).exp()
+    }
+
+    // undated pages get no boost, the crawl time says nothing about how fresh the content is
+    pub fn get_boost(&self, published: Option<DateTime<Utc>>, now: DateTime<Utc>) -> f64 {
+        let Some(published) = published else {
+            return 1.0;
+        };
+        if self.weight <= 0.0 {
+            return 1.0;
+        }
+        let age_days = (now - published).num_seconds() as f64 / 86400.0;
+        1.0 + self.weight * self.get_decay(age_days)
+    }
+}
+
+pub mod main {
+    use super::*;
+
+    pub fn get_decay_config() -> DecayConfig {
+        let get_param = |name: &str, default: &str| {
+            env::var(name)
+                .unwrap_or(String::from(default))
+                .parse::<f64>()
+                .unwrap()
+        };
+        DecayConfig {
+            weight: get_param("RECENCY_WEIGHT", "0.5"),
+            offset_days: get_param("RECENCY_OFFSET_DAYS", "1"),
+            scale_days: get_param("RECENCY_SCALE_DAYS", "30"),
+            decay: get_param("RECENCY_DECAY", "0.5"),
+        }
+    }
+
+    // rfc 3339, rfc 2822 (http headers) or a datetime without zone, taken as utc
+    fn parse_date_time(value: &str) -> Option<DateTime<Utc>> {
+        if let Ok(date) = DateTime::parse_from_rfc3339(value) {
+            return Some(date.with_timezone(&Utc));
+        }
+        if let Ok(date) = DateTime::parse_from_rfc2822(value) {
+            return Some(date.with_timezone(&Utc));
+        }
+        for format in ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S"] {
+            if let Ok(date) = NaiveDateTime::parse_from_str(value, format) {
+                return Some(date.and_utc());
+            }
+        }
+        Option::None
+    }
+
+    fn parse_day(value: &str) -> Option<NaiveDate> {
+        value.get(..10).and_then(|value| NaiveDate::parse_from_str(value, "%Y-%m-%d").ok())
+    }
+
+    // a datetime, or the start of a plain date
+    pub fn parse_date(value: &str) -> Option<DateTime<Utc>> {
+        let value = value.trim();
+        parse_date_time(value).or_else(|| Some(parse_day(value)?.and_hms_opt(0, 0, 0)?.and_utc()))
+    }
+
+    // a datetime, or the last millisecond of a plain date so a `before` filter takes in the
+    // whole day
+    pub fn parse_end_date(value: &str) -> Option<DateTime<Utc>> {
+        let value = value.trim();
+        parse_date_time(value).or_else(|| Some(parse_day(value)?.and_hms_milli_opt(23, 59, 59, 999)?.and_utc()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn gaussian_decay_follows_offset_and_scale() {
+        let config = DecayConfig {
+            weight: 1.0,
+            offset_days: 1.0,
+            scale_days: 30.0,
+            decay: 0.5,
+        };
+        let now = main::parse_date("2025-06-30T00:00:00Z").unwrap();
+        assert_eq!(config.get_boost(Some(now - Duration::hours(12)), now), 2.0);
+        let at_scale = config.get_boost(Some(now - Duration::days(31)), now);
+        assert!((at_scale - 1.5).abs() < 1e-9);
+        let old = config.get_boost(Some(now - Duration::days(365)), now);
+        assert!((1.0..1.001).contains(&old));
+        assert_eq!(config.get_boost(Option::None, now), 1.0);
+        // dated ahead of now, boosted like a brand new page and no more
+        assert_eq!(config.get_boost(Some(now + Duration::days(60)), now), 2.0);
+    }
+
+    #[test]
+    fn parse_date_accepts_page_and_header_formats() {
+        let expected = main::parse_date("2024-03-05T10:20:30Z").unwrap();
+        assert_eq!(main::parse_date("2024-03-05T11:20:30+01:00"), Some(expected));
+        assert_eq!(main::parse_date("Tue, 05 Mar 2024 10:20:30 GMT"), Some(expected));
+        assert_eq!(main::parse_date("2024-03-05T10:20:30"), Some(expected));
+        assert_eq!(
+            main::parse_date("2024-03-05"),
+            main::parse_date("2024-03-05T00:00:00Z")
+        );
+        assert_eq!(main::parse_date("yesterday"), Option::None);
+    }
+
+    #[test]
+    fn end_dates_take_in_the_whole_day() {
+        assert_eq!(main::parse_end_date("2024-03-05"), main::parse_date("2024-03-05T23:59:59.999Z"));
+        assert_eq!(main::parse_end_date("2024-03-05T10:20:30Z"), main::parse_date("2024-03-05T10:20:30Z"));
+        assert_eq!(main::parse_end_date("yesterday"), Option::None);
+    }
+}
@@ -0,0 +1,737 @@
+use crate::fuzzy::LevenshteinAutomaton;
+use crate::inverted_index::Field;
+use crate::postings::{PostingList, BLOCK_LEN};
+use lazy_static::lazy_static;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::env;
+use std::mem;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::Duration;
+
+// dense ids postings store instead of urls, a url keeps its id for the life of the process
+#[derive(Default)]
+pub struct DocIds {
+    urls: Vec<Arc<str>>,
+    ids: HashMap<Arc<str>, u32>,
+}
+
+// postings of one term while it is being built, in the buffer or by a merge
+#[derive(Clone, Debug, Default)]
+pub struct PostingsBuilder {
+    pub surface: String,
+    docs: BTreeSet<u32>,
+    // occurrences per document in each field
+    field_docs: HashMap<Field, BTreeMap<u32, u32>>,
+}
+
+impl PostingsBuilder {
+    pub fn new(surface: &str) -> PostingsBuilder {
+        PostingsBuilder {
+            surface: surface.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn add(&mut self, doc_id: u32, field: Field) {
+        self.add_freq(doc_id, field, 1);
+    }
+
+    pub fn add_freq(&mut self, doc_id: u32, field: Field, freq: u32) {
+        // site terms are only reachable through `site:`, so they stay out of the general postings
+        if field != Field::Site {
+            self.docs.insert(doc_id);
+        }
+        *self.field_docs.entry(field).or_default().entry(doc_id).or_insert(0) += freq;
+    }
+
+    fn remove(&mut self, doc_id: u32) {
+        self.docs.remove(&doc_id);
+        for docs in self.field_docs.values_mut() {
+            docs.remove(&doc_id);
+        }
+        self.field_docs.retain(|_, docs| !docs.is_empty());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.field_docs.is_empty()
+    }
+
+    pub fn build(self) -> TermPostings {
+        let mut field_docs = self
+            .field_docs
+            .into_iter()
+            .map(|(field, docs)| {
+                let (doc_ids, freqs): (Vec<u32>, Vec<u32>) = docs.into_iter().unzip();
+                (field, PostingList::from_sorted_with_freqs(&doc_ids, &freqs))
+            })
+            .collect::<Vec<(Field, PostingList)>>();
+        field_docs.shrink_to_fit();
+        TermPostings {
+            surface: self.surface,
+            docs: PostingList::from_sorted(&self.docs.into_iter().collect::<Vec<u32>>()),
+            field_docs,
+        }
+    }
+}
+
+// postings of one term in a segment
+#[derive(Clone, Debug, Default)]
+pub struct TermPostings {
+    // first unstemmed form seen for this term, shown in suggestions instead of the stem
+    pub surface: String,
+    // documents having the term in any field but site
+    pub docs: PostingList,
+    pub field_docs: Vec<(Field, PostingList)>,
+}
+
+impl TermPostings {
+    pub fn get_docs(&self, field: Option<Field>) -> Option<&PostingList> {
+        match field {
+            Some(field) => self
+                .field_docs
+                .iter()
+                .find(|(other, _)| *other == field)
+                .map(|(_, docs)| docs),
+            None => Some(&self.docs),
+        }
+    }
+}
+
+// never changed once built, terms are sorted so prefixes are a contiguous range
+#[derive(Debug)]
+pub struct Segment {
+    pub id: u64,
+    pub terms: Vec<(String, TermPostings)>,
+    pub docs: PostingList,
+}
+
+impl Segment {
+    pub fn new(id: u64, terms: HashMap<String, PostingsBuilder>, docs: BTreeSet<u32>) -> Segment {
+        let mut terms = terms
+            .into_iter()
+            .filter(|(_, postings)| !postings.is_empty())
+            .map(|(term, postings)| (term, postings.build()))
+            .collect::<Vec<(String, TermPostings)>>();
+        terms.sort_by(|a, b| a.0.cmp(&b.0));
+        let docs = PostingList::from_sorted(&docs.into_iter().collect::<Vec<u32>>());
+        Segment { id, terms, docs }
+    }
+
+    #[cfg(test)]
+    pub fn get_heap_size(&self) -> usize {
+        let postings = self.terms.iter().map(|(term, postings)| {
+            let lists = postings.field_docs.iter().map(|(_, docs)| docs.get_heap_size()).sum::<usize>();
+            let field_slots = postings.field_docs.capacity() * mem::size_of::<(Field, PostingList)>();
+            term.capacity() + postings.surface.capacity() + postings.docs.get_heap_size() + field_slots + lists
+        });
+        let term_slots = self.terms.capacity() * mem::size_of::<(String, TermPostings)>();
+        postings.sum::<usize>() + term_slots + self.docs.get_heap_size()
+    }
+
+    pub fn get(&self, text: &str) -> Option<&TermPostings> {
+        let idx = self.terms.binary_search_by(|(term, _)| term.as_str().cmp(text)).ok()?;
+        Some(&self.terms[idx].1)
+    }
+
+    pub fn get_prefix(&self, prefix: &str) -> &[(String, TermPostings)] {
+        let start = self.terms.partition_point(|(term, _)| term.as_str() < prefix);
+        let len = self.terms[start..]
+            .iter()
+            .take_while(|(term, _)| term.starts_with(prefix))
+            .count();
+        &self.terms[start..start + len]
+    }
+
+    // terms within the automaton's distance and their distance. the rows of a prefix are shared
+    // by every term starting with it, and once a prefix can't match anymore all of its terms
+    // are skipped with one search
+    pub fn get_fuzzy(&self, automaton: &LevenshteinAutomaton) -> Vec<(&str, &TermPostings, usize)> {
+        let mut matches = Vec::new();
+        // rows[idx] is the automaton state after the first idx chars of prefix
+        let mut rows = vec![automaton.start()];
+        let mut prefix: Vec<char> = Vec::new();
+        let mut idx = 0;
+        while idx < self.terms.len() {
+            let (term, postings) = &self.terms[idx];
+            let chars = term.chars().collect::<Vec<char>>();
+            let shared = prefix.iter().zip(chars.iter()).take_while(|(a, b)| a == b).count();
+            rows.truncate(shared + 1);
+            let mut dead_len = Option::None;
+            for (pos, c) in chars.iter().enumerate().skip(shared) {
+                let state = automaton.step(&rows[pos], *c);
+                if !automaton.can_match(&state) {
+                    dead_len = Some(pos + 1);
+                    break;
+                }
+                rows.push(state);
+            }
+            match dead_len {
+                Some(len) => {
+                    let dead_prefix = chars[..len].iter().collect::<String>();
+                    idx += self.terms[idx..].partition_point(|(term, _)| term.starts_with(&dead_prefix));
+                    prefix = chars[..len - 1].to_vec();
+                }
+                None => {
+                    let state = &rows[chars.len()];
+                    if automaton.is_match(state) {
+                        matches.push((term.as_str(), postings, state[state.len() - 1]));
+                    }
+                    prefix = chars;
+                    idx += 1;
+                }
+            }
+        }
+        matches
+    }
+}
+
+// a segment and the documents deleted from it since it was built, deletes are copy on write
+#[derive(Clone, Debug)]
+pub struct SegmentReader {
+    pub segment: Arc<Segment>,
+    pub deleted: Arc<HashSet<u32>>,
+}
+
+impl SegmentReader {
+    pub fn is_live(&self, doc_id: u32) -> bool {
+        !self.deleted.contains(&doc_id)
+    }
+
+    pub fn get_live_count(&self) -> usize {
+        self.segment.docs.len().saturating_sub(self.deleted.len())
+    }
+
+    pub fn get_doc_freq(&self, postings: &TermPostings, field: Option<Field>) -> usize {
+        let Some(docs) = postings.get_docs(field) else {
+            return 0;
+        };
+        // a lookup decodes a block, walking the list decodes all of them
+        let deleted = match self.deleted.len() * BLOCK_LEN < docs.len() {
+            true => self.deleted.iter().filter(|doc_id| docs.contains(**doc_id)).count(),
+            false => docs.iter().filter(|doc_id| self.deleted.contains(doc_id)).count(),
+        };
+        docs.len() - deleted
+    }
+}
+
+// what readers search, replaced as a whole by flushes, deletes and merges so a query never
+// sees half of a change. a document is live in at most one segment, adding a url deletes
+// its older versions
+#[derive(Clone, Debug, Default)]
+pub struct Snapshot {
+    // oldest first
+    pub segments: Vec<SegmentReader>,
+}
+
+impl Snapshot {
+    // the term's postings and its list of the field in every segment having it, deleted
+    // documents are still in them
+    pub fn get_lists(&self, text: &str, field: Option<Field>) -> Vec<(&SegmentReader, &TermPostings, &PostingList)> {
+        self.segments
+            .iter()
+            .filter_map(|reader| {
+                let postings = reader.segment.get(text)?;
+                Some((reader, postings, postings.get_docs(field)?))
+            })
+            .filter(|(_, _, docs)| !docs.is_empty())
+            .collect()
+    }
+
+    pub fn get_postings(&self, text: &str, field: Option<Field>) -> Vec<u32> {
+        self.get_lists(text, field)
+            .into_iter()
+            .flat_map(|(reader, _, docs)| docs.iter().filter(|doc_id| reader.is_live(*doc_id)))
+            .collect()
+    }
+
+    pub fn get_doc_freq(&self, text: &str, field: Option<Field>) -> usize {
+        self.segments
+            .iter()
+            .filter_map(|reader| Some(reader.get_doc_freq(reader.segment.get(text)?, field)))
+            .sum()
+    }
+
+    pub fn get_surface(&self, text: &str) -> Option<String> {
+        self.segments
+            .iter()
+            .find_map(|reader| reader.segment.get(text))
+            .map(|postings| postings.surface.to_string())
+    }
+
+    // every segment's terms starting with prefix, a term held by several segments is
+    // visited once per segment
+    pub fn visit_terms<'a>(&'a self, prefix: &str, mut visit: impl FnMut(&'a str, &'a SegmentReader, &'a TermPostings)) {
+        for reader in self.segments.iter() {
+            for (term, postings) in reader.segment.get_prefix(prefix) {
+                visit(term, reader, postings);
+            }
+        }
+    }
+
+    // every segment's terms within the automaton's distance, visited with the distance
+    pub fn visit_fuzzy_terms<'a>(
+        &'a self,
+        automaton: &LevenshteinAutomaton,
+        mut visit: impl FnMut(&'a str, &'a SegmentReader, &'a TermPostings, usize),
+    ) {
+        for reader in self.segments.iter() {
+            for (term, postings, distance) in reader.segment.get_fuzzy(automaton) {
+                visit(term, reader, postings, distance);
+            }
+        }
+    }
+}
+
+// documents added since the last flush, readers don't see them yet
+#[derive(Default)]
+pub struct Buffer {
+    terms: HashMap<String, PostingsBuilder>,
+    // terms of each buffered document, lets a delete find them without a scan
+    doc_terms: HashMap<u32, HashSet<String>>,
+}
+
+impl Buffer {
+    fn remove(&mut self, doc_id: u32) {
+        let Some(terms) = self.doc_terms.remove(&doc_id) else {
+            return;
+        };
+        for term in terms {
+            let Some(postings) = self.terms.get_mut(&term) else {
+                continue;
+            };
+            postings.remove(doc_id);
+            if postings.is_empty() {
+                self.terms.remove(&term);
+            }
+        }
+    }
+}
+
+lazy_static! {
+    static ref SNAPSHOT: RwLock<Arc<Snapshot>> = RwLock::new(Arc::new(Snapshot::default()));
+    // taken before SNAPSHOT by writers that touch both
+    static ref BUFFER: Mutex<Buffer> = Mutex::new(Buffer::default());
+    // one merge at a time, flushes and deletes carry on while it runs
+    static ref MERGE_LOCK: Mutex<()> = Mutex::new(());
+    static ref NEXT_SEGMENT_ID: AtomicU64 = AtomicU64::new(0);
+    static ref DOC_IDS: RwLock<DocIds> = RwLock::new(DocIds::default());
+}
+
+pub mod main {
+    use super::*;
+
+    fn get_env<T: std::str::FromStr>(name: &str, default: &str) -> T
+    where
+        T::Err: std::fmt::Debug,
+    {
+        env::var(name)
+            .unwrap_or(String::from(default))
+            .parse::<T>()
+            .unwrap()
+    }
+
+    // how often buffered documents become searchable
+    fn get_refresh_interval() -> Duration {
+        Duration::from_millis(get_env("SEGMENT_REFRESH_MS", "1000"))
+    }
+
+    // a full buffer is flushed by the insert that filled it
+    fn get_max_buffered_docs() -> usize {
+        get_env("SEGMENT_MAX_BUFFERED_DOCS", "1000")
+    }
+
+    // segments a merge combines, and the count above which the smallest ones are merged
+    fn get_merge_factor() -> usize {
+        get_env::<usize>("SEGMENT_MERGE_FACTOR", "4").max(2)
+    }
+
+    fn get_max_segments() -> usize {
+        get_env("SEGMENT_MAX_COUNT", "8")
+    }
+
+    // a segment with this share of deleted documents is rewritten without them
+    fn get_max_deleted_ratio() -> f64 {
+        get_env("SEGMENT_MAX_DELETED_RATIO", "0.3")
+    }
+
+    pub fn get_snapshot() -> Arc<Snapshot> {
+        SNAPSHOT.read().unwrap().clone()
+    }
+
+    fn get_next_id() -> u64 {
+        NEXT_SEGMENT_ID.fetch_add(1, Ordering::SeqCst)
+    }
+
+    // readers keep the snapshot they hold, the lock is only taken for the swap
+    fn update_snapshot(update: impl FnOnce(&mut Snapshot)) {
+        let mut snapshot_ref = SNAPSHOT.write().unwrap();
+        let mut snapshot = Snapshot::clone(&snapshot_ref);
+        update(&mut snapshot);
+        *snapshot_ref = Arc::new(snapshot);
+    }
+
+    pub fn get_doc_id(url: &str) -> Option<u32> {
+        DOC_IDS.read().unwrap().ids.get(url).copied()
+    }
+
+    pub fn add_doc_id(url: &str) -> u32 {
+        if let Some(doc_id) = get_doc_id(url) {
+            return doc_id;
+        }
+        let mut doc_ids = DOC_IDS.write().unwrap();
+        if let Some(doc_id) = doc_ids.ids.get(url) {
+            return *doc_id;
+        }
+        let doc_id = doc_ids.urls.len() as u32;
+        let url = Arc::<str>::from(url);
+        doc_ids.urls.push(url.clone());
+        doc_ids.ids.insert(url, doc_id);
+        doc_id
+    }
+
+    pub fn get_url(doc_id: u32) -> Option<String> {
+        DOC_IDS.read().unwrap().urls.get(doc_id as usize).map(|url| url.to_string())
+    }
+
+    fn delete_from_segments(snapshot: &mut Snapshot, doc_id: u32) {
+        for reader in snapshot.segments.iter_mut() {
+            if reader.is_live(doc_id) && reader.segment.docs.contains(doc_id) {
+                Arc::make_mut(&mut reader.deleted).insert(doc_id);
+            }
+        }
+    }
+
+    // replaces every version of the document. the old one stays searchable until the next
+    // flush shadows it. tokens are (field, surface form, term)
+    pub fn add_document(url: &str, tokens: Vec<(Field, String, String)>) {
+        let doc_id = add_doc_id(url);
+        let mut buffer = BUFFER.lock().unwrap();
+        buffer.remove(doc_id);
+        let mut doc_terms = HashSet::new();
+        for (field, surface, term) in tokens {
+            let postings = buffer
+                .terms
+                .entry(term.to_string())
+                .or_insert_with(|| PostingsBuilder::new(&surface));
+            postings.add(doc_id, field);
+            doc_terms.insert(term);
+        }
+        buffer.doc_terms.insert(doc_id, doc_terms);
+        if buffer.doc_terms.len() >= get_max_buffered_docs() {
+            flush_buffer(&mut buffer);
+        }
+    }
+
+    pub fn delete_document(url: &str) {
+        let Some(doc_id) = get_doc_id(url) else {
+            return;
+        };
+        let mut buffer = BUFFER.lock().unwrap();
+        buffer.remove(doc_id);
+        update_snapshot(|snapshot| delete_from_segments(snapshot, doc_id));
+    }
+
+    fn flush_buffer(buffer: &mut Buffer) {
+        if buffer.doc_terms.is_empty() {
+            return;
+        }
+        let Buffer { terms, doc_terms } = mem::take(buffer);
+        let segment = Segment::new(get_next_id(), terms, doc_terms.into_keys().collect());
+        let reader = SegmentReader {
+            segment: Arc::new(segment),
+            deleted: Arc::new(HashSet::new()),
+        };
+        // older versions go in the same swap, so a reader sees exactly one of them
+        update_snapshot(|snapshot| {
+            for doc_id in reader.segment.docs.iter() {
+                delete_from_segments(snapshot, doc_id);
+            }
+            snapshot.segments.push(reader);
+        });
+    }
+
+    // makes every added document searchable
+    pub fn flush() {
+        flush_buffer(&mut BUFFER.lock().unwrap());
+    }
+
+    // swaps in a loaded index, documents added before it stay in the buffer
+    pub fn replace_segments(terms: HashMap<String, PostingsBuilder>, docs: BTreeSet<u32>) {
+        let _merge_lock = MERGE_LOCK.lock().unwrap();
+        let reader = SegmentReader {
+            segment: Arc::new(Segment::new(get_next_id(), terms, docs)),
+            deleted: Arc::new(HashSet::new()),
+        };
+        update_snapshot(|snapshot| snapshot.segments = vec![reader]);
+    }
+
+    // (id, live docs, deleted docs) of each segment. picks every segment with too many
+    // deletes, or the merge factor smallest once there are too many segments
+    pub fn get_merge_candidates(segments: &[(u64, usize, usize)]) -> Vec<u64> {
+        let max_deleted_ratio = get_max_deleted_ratio();
+        let mut candidates = segments
+            .iter()
+            .filter(|(_, live, deleted)| {
+                *deleted > 0 && *deleted as f64 >= (*live + *deleted) as f64 * max_deleted_ratio
+            })
+            .map(|(id, _, _)| *id)
+            .collect::<Vec<u64>>();
+        if segments.len() > get_max_segments() {
+            let mut by_size = segments.to_vec();
+            by_size.sort_by_key(|(id, live, _)| (*live, *id));
+            for (id, _, _) in by_size.into_iter().take(get_merge_factor()) {
+                if !candidates.contains(&id) {
+                    candidates.push(id);
+                }
+            }
+        }
+        candidates
+    }
+
+    // the live documents of the given segments as one new segment
+    pub fn merge_segments(readers: &[SegmentReader], id: u64) -> Segment {
+        let mut terms: HashMap<String, PostingsBuilder> = HashMap::new();
+        let mut docs = BTreeSet::new();
+        for reader in readers {
+            docs.extend(reader.segment.docs.iter().filter(|doc_id| reader.is_live(*doc_id)));
+            for (term, postings) in reader.segment.terms.iter() {
+                let merged = terms
+                    .entry(term.to_string())
+                    .or_insert_with(|| PostingsBuilder::new(&postings.surface));
+                for (field, field_docs) in postings.field_docs.iter() {
+                    for (doc_id, freq) in field_docs.iter().zip(field_docs.get_freqs()) {
+                        if reader.is_live(doc_id) {
+                            merged.add_freq(doc_id, *field, freq);
+                        }
+                    }
+                }
+            }
+        }
+        Segment::new(id, terms, docs)
+    }
+
+    // builds the merged segment from a snapshot without blocking readers or writers, then
+    // carries over the deletes that happened meanwhile
+    pub fn merge() -> bool {
+        let _merge_lock = MERGE_LOCK.lock().unwrap();
+        let snapshot = get_snapshot();
+        let sizes = snapshot
+            .segments
+            .iter()
+            .map(|reader| (reader.segment.id, reader.get_live_count(), reader.deleted.len()))
+            .collect::<Vec<(u64, usize, usize)>>();
+        let candidates = get_merge_candidates(&sizes);
+        if candidates.is_empty() {
+            return false;
+        }
+        let readers = snapshot
+            .segments
+            .iter()
+            .filter(|reader| candidates.contains(&reader.segment.id))
+            .cloned()
+            .collect::<Vec<SegmentReader>>();
+        // the oldest source's id keeps the merged segment where its documents were
+        let id = readers.iter().map(|reader| reader.segment.id).min().unwrap();
+        let merged = merge_segments(&readers, id);
+        update_snapshot(|snapshot| {
+            let mut deleted = HashSet::new();
+            for reader in snapshot.segments.iter() {
+                let Some(source) = readers.iter().find(|source| source.segment.id == reader.segment.id) else {
+                    continue;
+                };
+                deleted.extend(reader.deleted.difference(&source.deleted).cloned());
+            }
+            let reader = SegmentReader {
+                segment: Arc::new(merged),
+                deleted: Arc::new(deleted),
+            };
+            let position = snapshot
+                .segments
+                .iter()
+                .position(|reader| reader.segment.id == id)
+                .unwrap_or(0);
+            snapshot.segments.retain(|reader| !candidates.contains(&reader.segment.id));
+            snapshot.segments.insert(position.min(snapshot.segments.len()), reader);
+        });
+        true
+    }
+
+    // refreshes on an interval and merges until the policy has nothing left to pick
+    pub fn start_background() {
+        let interval = get_refresh_interval();
+        thread::spawn(move || {
+            loop {
+                thread::sleep(interval);
+                flush();
+                while merge() {}
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::url_index;
+
+    fn get_segment(id: u64, docs: &[(u32, &str)]) -> SegmentReader {
+        let mut terms: HashMap<String, PostingsBuilder> = HashMap::new();
+        for (doc_id, text) in docs {
+            for word in text.split_whitespace() {
+                terms.entry(word.to_string()).or_default().add(*doc_id, Field::Content);
+            }
+        }
+        let doc_ids = docs.iter().map(|(doc_id, _)| *doc_id).collect();
+        SegmentReader {
+            segment: Arc::new(Segment::new(id, terms, doc_ids)),
+            deleted: Arc::new(HashSet::new()),
+        }
+    }
+
+    #[test]
+    fn merge_purges_deleted_documents() {
+        let mut first = get_segment(0, &[(0, "red fox"), (1, "red hen")]);
+        let mut second = get_segment(1, &[(2, "blue fox"), (0, "red fox jumps")]);
+        Arc::make_mut(&mut first.deleted).insert(0);
+        let snapshot = Snapshot {
+            segments: vec![first.clone(), second.clone()],
+        };
+        assert_eq!(snapshot.get_doc_freq("fox", Option::None), 2);
+        assert_eq!(snapshot.get_doc_freq("red", Some(Field::Content)), 2);
+        assert_eq!(snapshot.get_postings("red", Option::None), vec![1, 0]);
+        assert_eq!(snapshot.segments[0].get_live_count(), 1);
+
+        Arc::make_mut(&mut second.deleted).insert(2);
+        let merged = main::merge_segments(&[first, second], 0);
+        assert_eq!(merged.docs.iter().collect::<Vec<u32>>(), vec![0, 1]);
+        assert!(merged.get("blue").is_none());
+        assert_eq!(merged.get("fox").unwrap().docs.iter().collect::<Vec<u32>>(), vec![0]);
+        let prefix = merged.get_prefix("j").iter().map(|(term, _)| term.as_str()).collect::<Vec<&str>>();
+        assert_eq!(prefix, vec!["jumps"]);
+    }
+
+    #[test]
+    fn fuzzy_walk_finds_the_same_terms_as_a_full_scan() {
+        let text = "rust rusty rusted trust rest roast crust rust-lang rüst ru r ferrous bust dust \
+                    rustacean rusts just rusk russet gust";
+        let reader = get_segment(0, &[(0, text)]);
+        for (query, max_distance) in [("rust", 1), ("rust", 2), ("rusty", 2), ("xyz", 1), ("r", 1)] {
+            let automaton = LevenshteinAutomaton::new(query, max_distance);
+            let mut scanned = Vec::new();
+            for (term, _) in reader.segment.terms.iter() {
+                let state = term.chars().fold(automaton.start(), |state, c| automaton.step(&state, c));
+                if automaton.is_match(&state) {
+                    scanned.push((term.as_str(), state[state.len() - 1]));
+                }
+            }
+            let walked = reader
+                .segment
+                .get_fuzzy(&automaton)
+                .into_iter()
+                .map(|(term, _, distance)| (term, distance))
+                .collect::<Vec<(&str, usize)>>();
+            assert_eq!(walked, scanned, "{query} within {max_distance}");
+        }
+    }
+
+    // what a hash set of urls holds on the heap: a slot and a control byte per bucket, plus
+    // the bytes of every string
+    fn get_url_set_size(urls: &HashSet<String>) -> usize {
+        urls.capacity() * (mem::size_of::<String>() + 1) + urls.iter().map(String::capacity).sum::<usize>()
+    }
+
+    // a segment of a synthetic corpus measured with get_heap_size, plus the url to id table,
+    // against the url sets per term and field postings were kept in before they were
+    // compressed, whose size is estimated from their capacity
+    #[test]
+    fn segment_postings_are_smaller_than_url_sets() {
+        let doc_count = 2_000u32;
+        let urls = (0..doc_count)
+            .map(|id| format!("https://site{}.example.com/articles/{}/page-{}", id % 50, id / 50, id))
+            .collect::<Vec<String>>();
+        // term t is in every (t % 200 + 1)th document, a few common terms and a long tail of
+        // rare ones, around 150 distinct terms per document
+        let term_docs = (0..5_000u32)
+            .map(|term| {
+                let step = term % 200 + 1;
+                (term % step..doc_count).step_by(step as usize).collect::<Vec<u32>>()
+            })
+            .collect::<Vec<Vec<u32>>>();
+
+        let mut url_sets_size = 0;
+        for (term, doc_ids) in term_docs.iter().enumerate() {
+            let term_urls = doc_ids.iter().map(|id| urls[*id as usize].to_string()).collect::<HashSet<String>>();
+            // the same urls again under the content field
+            let field_urls = HashMap::from([(Field::Content, term_urls.clone())]);
+            url_sets_size += format!("term{term}").capacity() * 2
+                + get_url_set_size(&term_urls)
+                + field_urls.capacity() * (mem::size_of::<(Field, HashSet<String>)>() + 1)
+                + field_urls.values().map(get_url_set_size).sum::<usize>();
+        }
+
+        let mut terms = HashMap::new();
+        for (term, doc_ids) in term_docs.iter().enumerate() {
+            let mut postings = PostingsBuilder::new(&format!("term{term}"));
+            doc_ids.iter().for_each(|doc_id| postings.add(*doc_id, Field::Content));
+            terms.insert(format!("term{term}"), postings);
+        }
+        let segment = Segment::new(0, terms, (0..doc_count).collect());
+        let mut doc_ids = DocIds::default();
+        for (doc_id, url) in urls.iter().enumerate() {
+            let url = Arc::<str>::from(url.as_str());
+            doc_ids.urls.push(url.clone());
+            doc_ids.ids.insert(url, doc_id as u32);
+        }
+        let id_table_size = doc_ids.urls.capacity() * mem::size_of::<Arc<str>>()
+            + doc_ids.ids.capacity() * (mem::size_of::<(Arc<str>, u32)>() + 1)
+            + urls.iter().map(|url| url.len() + 2 * mem::size_of::<usize>()).sum::<usize>();
+        let segment_size = segment.get_heap_size() + id_table_size;
+
+        assert!(
+            segment_size * 10 < url_sets_size,
+            "url sets : {} KiB, segment and id table : {} KiB",
+            url_sets_size / 1024,
+            segment_size / 1024
+        );
+        for (term, doc_ids) in term_docs.iter().enumerate().step_by(97) {
+            let postings = segment.get(&format!("term{term}")).unwrap();
+            assert_eq!(postings.docs.iter().collect::<Vec<u32>>(), *doc_ids);
+            assert_eq!(postings.get_docs(Some(Field::Content)).unwrap().iter().collect::<Vec<u32>>(), *doc_ids);
+        }
+    }
+
+    #[test]
+    fn replaced_document_stays_searchable_until_the_flush_shadows_it() {
+        let _lock = url_index::TEST_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+        let url = "https://shadow-test.example/";
+        let get_tokens = |term: &str| vec![(Field::Content, term.to_string(), term.to_string())];
+        main::add_document(url, get_tokens("shadowold"));
+        main::flush();
+        let doc_id = main::get_doc_id(url).unwrap();
+
+        main::add_document(url, get_tokens("shadownew"));
+        let snapshot = main::get_snapshot();
+        assert_eq!(snapshot.get_postings("shadowold", Option::None), vec![doc_id]);
+        assert!(snapshot.get_postings("shadownew", Option::None).is_empty());
+
+        main::flush();
+        let snapshot = main::get_snapshot();
+        assert!(snapshot.get_postings("shadowold", Option::None).is_empty());
+        assert_eq!(snapshot.get_postings("shadownew", Option::None), vec![doc_id]);
+
+        main::delete_document(url);
+        assert!(main::get_snapshot().get_postings("shadownew", Option::None).is_empty());
+    }
+
+    #[test]
+    fn merge_policy_picks_deletes_and_small_segments() {
+        assert!(main::get_merge_candidates(&[(0, 100, 0), (1, 10, 1)]).is_empty());
+        assert_eq!(main::get_merge_candidates(&[(0, 100, 0), (1, 10, 5)]), vec![1]);
+        let segments = (0..9).map(|id| (id, 100 - id as usize, 0)).collect::<Vec<(u64, usize, usize)>>();
+        let mut candidates = main::get_merge_candidates(&segments);
+        candidates.sort();
+        assert_eq!(candidates, vec![5, 6, 7, 8]);
+    }
+}
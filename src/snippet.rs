@@ -0,0 +1,200 @@
+use crate::analyzer::{self, ANALYZER};
+use crate::language::Language;
+use crate::url_index;
+use std::collections::HashSet;
+use std::env;
+use unicode_segmentation::UnicodeSegmentation;
+
+// a word of the stored text, offsets are bytes into that text
+pub struct SnippetWord {
+    pub start: usize,
+    pub end: usize,
+    pub is_match: bool,
+}
+
+pub mod main {
+    use super::*;
+
+    fn get_window_words() -> usize {
+        env::var("SNIPPET_WINDOW_WORDS")
+            .unwrap_or(String::from("30"))
+            .parse::<usize>()
+            .unwrap()
+    }
+
+    fn get_max_fragments() -> usize {
+        env::var("SNIPPET_MAX_FRAGMENTS")
+            .unwrap_or(String::from("2"))
+            .parse::<usize>()
+            .unwrap()
+    }
+
+    pub fn escape_html(text: &str) -> String {
+        text.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+            .replace('"', "&quot;")
+            .replace('\'', "&#39;")
+    }
+
+    // cjk text is indexed as bigrams, a character is marked when a bigram it is part of matched
+    fn is_cjk_match(words: &[(usize, &str)], idx: usize, terms: &HashSet<String>, language: Language) -> bool {
+        let is_adjacent = |a: usize, b: usize| words[a].0 + words[a].1.len() == words[b].0;
+        let is_term = |text: String| ANALYZER.filter(&text, language).is_some_and(|term| terms.contains(&term));
+        let is_cjk_word = |idx: usize| words[idx].1.chars().all(analyzer::is_cjk);
+        if is_term(words[idx].1.to_string()) {
+            return true;
+        }
+        if idx > 0
+            && is_cjk_word(idx - 1)
+            && is_adjacent(idx - 1, idx)
+            && is_term(format!("{}{}", words[idx - 1].1, words[idx].1))
+        {
+            return true;
+        }
+        idx + 1 < words.len()
+            && is_cjk_word(idx + 1)
+            && is_adjacent(idx, idx + 1)
+            && is_term(format!("{}{}", words[idx].1, words[idx + 1].1))
+    }
+
+    pub fn get_words(text: &str, terms: &HashSet<String>, language: Language) -> Vec<SnippetWord> {
+        let words = text.unicode_word_indices().collect::<Vec<(usize, &str)>>();
+        (0..words.len())
+            .map(|idx| {
+                let (start, word) = words[idx];
+                let is_match = match ANALYZER.cjk_bigrams && word.chars().all(analyzer::is_cjk) {
+                    true => is_cjk_match(&words, idx, terms, language),
+                    false => ANALYZER.filter(word, language).is_some_and(|term| terms.contains(&term)),
+                };
+                SnippetWord {
+                    start,
+                    end: start + word.len(),
+                    is_match,
+                }
+            })
+            .collect()
+    }
+
+    // distinct matched words count far more than repeats of the same one
+    fn get_window_score(text: &str, words: &[SnippetWord]) -> usize {
+        let matches = words.iter().filter(|word| word.is_match);
+        let distinct = matches
+            .clone()
+            .map(|word| text[word.start..word.end].to_lowercase())
+            .collect::<HashSet<String>>();
+        distinct.len() * 10 + matches.count()
+    }
+
+    // best non overlapping windows, each starting a couple of words before a match, in text order
+    pub fn get_windows(text: &str, words: &[SnippetWord], size: usize, count: usize) -> Vec<(usize, usize)> {
+        let mut candidates = words
+            .iter()
+            .enumerate()
+            .filter(|(_, word)| word.is_match)
+            .map(|(idx, _)| {
+                let start = idx.saturating_sub(2);
+                let end = (start + size).min(words.len());
+                (get_window_score(text, &words[start..end]), start, end)
+            })
+            .collect::<Vec<(usize, usize, usize)>>();
+        candidates.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+        let mut windows: Vec<(usize, usize)> = Vec::new();
+        for (_, start, end) in candidates {
+            if windows.len() >= count {
+                break;
+            }
+            if windows.iter().any(|(curr_start, curr_end)| start < *curr_end && *curr_start < end) {
+                continue;
+            }
+            windows.push((start, end));
+        }
+        windows.sort();
+        windows
+    }
+
+    // the window as escaped html with matched words wrapped in <mark>
+    pub fn get_fragment(text: &str, words: &[SnippetWord], start: usize, end: usize) -> String {
+        let mut fragment = String::new();
+        let mut offset = words[start].start;
+        for word in words[start..end].iter() {
+            fragment.push_str(&escape_html(&text[offset..word.start]));
+            let word_text = escape_html(&text[word.start..word.end]);
+            match word.is_match {
+                true => fragment.push_str(&format!("<mark>{word_text}</mark>")),
+                false => fragment.push_str(&word_text),
+            }
+            offset = word.end;
+        }
+        if end == words.len() {
+            fragment.push_str(&escape_html(&text[offset..]));
+        }
+        let fragment = fragment.split_whitespace().collect::<Vec<&str>>().join(" ");
+        let prefix = if start > 0 { "… " } else { "" };
+        let suffix = if end < words.len() { " …" } else { "" };
+        format!("{prefix}{fragment}{suffix}")
+    }
+
+    pub fn get_snippets(text: &str, terms: &HashSet<String>, language: Language) -> Vec<String> {
+        let words = get_words(text, terms, language);
+        get_windows(text, &words, get_window_words(), get_max_fragments())
+            .into_iter()
+            .map(|(start, end)| get_fragment(text, &words, start, end))
+            .collect()
+    }
+
+    // the stored document is read in place, only the fragments are copied out
+    pub fn get_snippets_by_url(url: &str, terms: &HashSet<String>) -> Vec<String> {
+        url_index::main::get_with(url, |data_node| get_node_snippets(data_node, terms)).unwrap_or_default()
+    }
+
+    // content first, then headings, and the opening of the content when neither has a match
+    fn get_node_snippets(data_node: &url_index::Node, terms: &HashSet<String>) -> Vec<String> {
+        for text in [&data_node.content, &data_node.headings] {
+            let snippets = get_snippets(text, terms, data_node.language);
+            if !snippets.is_empty() {
+                return snippets;
+            }
+        }
+        let words = get_words(&data_node.content, &HashSet::new(), data_node.language);
+        if words.is_empty() {
+            return vec![];
+        }
+        let end = get_window_words().min(words.len());
+        vec![get_fragment(&data_node.content, &words, 0, end)]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_terms(text: &str) -> HashSet<String> {
+        analyzer::main::analyze_query(text, Language::English)
+            .into_iter()
+            .collect()
+    }
+
+    #[test]
+    fn snippet_marks_stemmed_matches_and_escapes_html() {
+        let text = "Cooking <tips> & tricks. Installing Rust is easy: run rustup and the installer does the rest.";
+        let snippets = main::get_snippets(text, &get_terms("install rust"), Language::English);
+        assert_eq!(snippets.len(), 1);
+        assert_eq!(
+            snippets[0],
+            "… tips&gt; &amp; tricks. <mark>Installing</mark> <mark>Rust</mark> is easy: run rustup \
+             and the <mark>installer</mark> does the rest."
+        );
+    }
+
+    #[test]
+    fn snippet_prefers_window_with_more_distinct_terms() {
+        let filler = vec!["filler"; 40].join(" ");
+        let text = format!("rust rust rust {filler} rust memory safety {filler}");
+        let words = main::get_words(&text, &get_terms("rust memory safety"), Language::English);
+        let windows = main::get_windows(&text, &words, 10, 1);
+        assert_eq!(windows.len(), 1);
+        let fragment = main::get_fragment(&text, &words, windows[0].0, windows[0].1);
+        assert!(fragment.contains("<mark>memory</mark> <mark>safety</mark>"));
+    }
+}
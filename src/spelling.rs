@@ -0,0 +1,170 @@
+use crate::analyzer;
+use crate::fuzzy;
+use crate::inverted_index::{self, Field};
+use crate::language::Language;
+use crate::query;
+use std::env;
+
+pub mod main {
+    use super::*;
+
+    fn get_min_freq_ratio() -> usize {
+        env::var("SPELL_MIN_FREQ_RATIO")
+            .unwrap_or(String::from("10"))
+            .parse::<usize>()
+            .unwrap()
+    }
+
+    pub fn is_weak(result_count: usize) -> bool {
+        let min_results = env::var("SPELL_SUGGEST_MIN_RESULTS")
+            .unwrap_or(String::from("3"))
+            .parse::<usize>()
+            .unwrap();
+        result_count < min_results
+    }
+
+    pub fn correct_word(word: &str, field: Option<Field>) -> Option<String> {
+        if word.contains(['*', '?']) {
+            return Option::None;
+        }
+        let doc_freq = inverted_index::main::get_doc_freq_by_text(word, field);
+        // picks the neighbour with the best frequency discounted by edit distance
+        let best = inverted_index::main::fuzzy_expand_term(word, field, 50)
+            .into_iter()
+            .max_by(|a, b| {
+                let a_score = a.1 as f64 * fuzzy::main::get_fuzzy_weight(a.2);
+                let b_score = b.1 as f64 * fuzzy::main::get_fuzzy_weight(b.2);
+                a_score.total_cmp(&b_score)
+            });
+        let (text, best_freq, _) = best?;
+        // a known word is only replaced by a far more common neighbour
+        if doc_freq == 0 || best_freq >= doc_freq * get_min_freq_ratio() {
+            return Some(text);
+        }
+        Option::None
+    }
+
+    // a word is kept when one of the languages leaves it alone, otherwise it takes the most
+    // common correction any of them offers
+    fn correct_text(text: &str, field: Option<Field>, languages: &[Language]) -> Option<String> {
+        let mut is_corrected = false;
+        let mut words = Vec::new();
+        for surface in analyzer::ANALYZER.tokenize_query(text) {
+            let mut is_kept = false;
+            let mut corrections = Vec::new();
+            for language in languages {
+                for (_, word) in analyzer::ANALYZER.analyze_query(&surface, *language) {
+                    match correct_word(&word, field) {
+                        Some(corrected) => corrections.push(corrected),
+                        None => is_kept = true,
+                    }
+                }
+            }
+            let best = corrections
+                .into_iter()
+                .max_by_key(|corrected| inverted_index::main::get_doc_freq_by_text(corrected, field));
+            match best {
+                Some(corrected) if !is_kept => {
+                    is_corrected = true;
+                    words.push(inverted_index::main::get_surface_form(&corrected));
+                }
+                _ => words.push(surface),
+            }
+        }
+        match is_corrected {
+            true => Some(words.join(" ")),
+            false => Option::None,
+        }
+    }
+
+    pub fn get_suggestion(text: &str, languages: &[Language]) -> Option<String> {
+        let mut is_corrected = false;
+        let mut corrected_words = Vec::new();
+        for word in text.to_lowercase().split_whitespace() {
+            let scoped = word
+                .split_once(':')
+                .and_then(|(name, value)| query::main::get_field(name).map(|field| (name, field, value)));
+            let corrected = match scoped {
+                Some((_, Field::Site, _)) => Option::None,
+                Some((name, field, value)) => {
+                    correct_text(value, Some(field), languages).map(|value| format!("{name}:{value}"))
+                }
+                None => correct_text(word, Option::None, languages),
+            };
+            match corrected {
+                Some(corrected) => {
+                    is_corrected = true;
+                    corrected_words.push(corrected);
+                }
+                None => corrected_words.push(word.to_string()),
+            }
+        }
+        match is_corrected {
+            true => Some(corrected_words.join(" ")),
+            false => Option::None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::url_index;
+
+    fn add_page(url: &str, text: &str) {
+        url_index::main::insert(url, text, text, "", "", Language::English, Option::None, "").unwrap();
+        inverted_index::main::insert_by_content(url, text, text, "", "", Language::English);
+    }
+
+    // marzipan in ten pages, marzipam in one and marzipat in two
+    fn add_pages() -> Vec<String> {
+        let mut urls = Vec::new();
+        for (text, count) in [("marzipan", 10), ("marzipam", 1), ("marzipat", 2)] {
+            for page in 0..count {
+                let url = format!("https://spelling-test.example/{text}/{page}");
+                add_page(&url, text);
+                urls.push(url);
+            }
+        }
+        urls
+    }
+
+    fn delete_pages(urls: Vec<String>) {
+        for url in urls {
+            assert!(inverted_index::main::delete_document(&url).unwrap());
+        }
+    }
+
+    #[test]
+    fn known_words_need_a_far_more_common_neighbour() {
+        let _lock = url_index::TEST_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+        let urls = add_pages();
+        crate::segment::main::flush();
+
+        // ten times as common
+        assert_eq!(main::correct_word("marzipam", Option::None), Some(String::from("marzipan")));
+        // only five times
+        assert_eq!(main::correct_word("marzipat", Option::None), Option::None);
+        assert_eq!(main::correct_word("marzipax", Option::None), Some(String::from("marzipan")));
+        assert_eq!(main::correct_word("marzip*", Option::None), Option::None);
+        assert_eq!(main::correct_word("marzipa?", Option::None), Option::None);
+
+        delete_pages(urls);
+    }
+
+    #[test]
+    fn suggestions_keep_field_scopes_and_leave_sites_alone() {
+        let _lock = url_index::TEST_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+        let urls = add_pages();
+        crate::segment::main::flush();
+
+        let languages = [Language::English, Language::German];
+        assert_eq!(
+            main::get_suggestion("title:Marzipax site:marzipax.example the marzipat", &languages),
+            Some(String::from("title:marzipan site:marzipax.example the marzipat"))
+        );
+        assert_eq!(main::get_suggestion("marzipan site:marzipax.example", &languages), Option::None);
+
+        delete_pages(urls);
+    }
+}
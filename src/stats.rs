@@ -0,0 +1,293 @@
+use crate::inverted_index::{self, Field};
+use crate::language::Language;
+use crate::url_index::FieldCount;
+use lazy_static::lazy_static;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+
+// what a single document contributes to the corpus statistics
+#[derive(Clone, Debug, Default)]
+pub struct DocStats {
+    pub field_lengths: FieldCount,
+    pub field_terms: HashMap<Field, HashSet<String>>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct TermStats {
+    // documents containing the term in any scored field
+    pub doc_freq: u64,
+    pub field_doc_freq: HashMap<Field, u64>,
+}
+
+#[derive(Debug, Default)]
+pub struct CorpusStats {
+    pub total_count: u64,
+    pub field_count: FieldCount,
+    pub terms: HashMap<String, TermStats>,
+}
+
+#[derive(Serialize)]
+pub struct AvgFieldLen {
+    pub url: f64,
+    pub title: f64,
+    pub headings: f64,
+    pub highlighted: f64,
+    pub content: f64,
+}
+
+#[derive(Serialize)]
+pub struct StatsSummary {
+    pub total_count: u64,
+    pub term_count: usize,
+    pub field_count: FieldCount,
+    pub avg_field_len: AvgFieldLen,
+}
+
+impl FieldCount {
+    pub fn get(&self, field: Field) -> u64 {
+        match field {
+            Field::Url => self.url,
+            Field::Title => self.title,
+            Field::Headings => self.headings,
+            Field::Highlighted => self.highlighted,
+            Field::Content => self.content,
+            Field::Site => 0,
+        }
+    }
+
+    pub fn add(&mut self, delta: &FieldCount) {
+        self.url += delta.url;
+        self.title += delta.title;
+        self.headings += delta.headings;
+        self.highlighted += delta.highlighted;
+        self.content += delta.content;
+    }
+
+    fn sub(&mut self, delta: &FieldCount) {
+        self.url = self.url.saturating_sub(delta.url);
+        self.title = self.title.saturating_sub(delta.title);
+        self.headings = self.headings.saturating_sub(delta.headings);
+        self.highlighted = self.highlighted.saturating_sub(delta.highlighted);
+        self.content = self.content.saturating_sub(delta.content);
+    }
+}
+
+impl DocStats {
+    fn get_terms(&self) -> HashSet<&String> {
+        self.field_terms.values().flatten().collect()
+    }
+}
+
+impl CorpusStats {
+    pub fn add(&mut self, doc: &DocStats) {
+        self.total_count += 1;
+        self.field_count.add(&doc.field_lengths);
+        for term in doc.get_terms() {
+            self.terms.entry(term.to_string()).or_default().doc_freq += 1;
+        }
+        for (field, terms) in doc.field_terms.iter() {
+            for term in terms {
+                let term_stats = self.terms.entry(term.to_string()).or_default();
+                *term_stats.field_doc_freq.entry(*field).or_insert(0) += 1;
+            }
+        }
+    }
+
+    pub fn remove(&mut self, doc: &DocStats) {
+        self.total_count = self.total_count.saturating_sub(1);
+        self.field_count.sub(&doc.field_lengths);
+        for (field, terms) in doc.field_terms.iter() {
+            for term in terms {
+                let Some(term_stats) = self.terms.get_mut(term) else {
+                    continue;
+                };
+                if let Some(count) = term_stats.field_doc_freq.get_mut(field) {
+                    *count = count.saturating_sub(1);
+                    if *count == 0 {
+                        term_stats.field_doc_freq.remove(field);
+                    }
+                }
+            }
+        }
+        for term in doc.get_terms() {
+            let Some(term_stats) = self.terms.get_mut(term) else {
+                continue;
+            };
+            term_stats.doc_freq = term_stats.doc_freq.saturating_sub(1);
+            if term_stats.doc_freq == 0 {
+                self.terms.remove(term);
+            }
+        }
+    }
+
+    // a field-scoped term (`title:rust`) counts only the documents having it in that field
+    pub fn get_doc_freq(&self, term: &str, field: Option<Field>) -> u64 {
+        let Some(term_stats) = self.terms.get(term) else {
+            return 0;
+        };
+        match field {
+            Some(field) => term_stats.field_doc_freq.get(&field).copied().unwrap_or(0),
+            None => term_stats.doc_freq,
+        }
+    }
+
+    // a copy holding only the given terms, lets a query score without keeping the lock
+    pub fn get_snapshot(&self, terms: &[&str]) -> CorpusStats {
+        let terms = terms
+            .iter()
+            .filter_map(|term| Some((term.to_string(), self.terms.get(*term)?.clone())))
+            .collect();
+        CorpusStats {
+            total_count: self.total_count,
+            field_count: self.field_count,
+            terms,
+        }
+    }
+
+    pub fn get_avg_field_len(&self, field: Field) -> f64 {
+        if self.total_count == 0 {
+            return 0.0;
+        }
+        self.field_count.get(field) as f64 / self.total_count as f64
+    }
+}
+
+lazy_static! {
+    pub static ref CORPUS_STATS: Arc<RwLock<CorpusStats>> = Arc::new(RwLock::new(CorpusStats::default()));
+}
+
+pub mod main {
+    use super::*;
+
+    // replaces the stats with ones computed from the whole corpus
+    pub fn set(total_count: u64, field_count: FieldCount, terms: HashMap<String, TermStats>) {
+        *CORPUS_STATS.write().unwrap() = CorpusStats {
+            total_count,
+            field_count,
+            terms,
+        };
+    }
+
+    pub fn get_doc_stats(
+        url: &str,
+        content: &str,
+        title: &str,
+        headings: &str,
+        highlighted: &str,
+        language: Language,
+    ) -> DocStats {
+        let mut field_lengths = FieldCount::default();
+        let mut field_terms = HashMap::new();
+        for field in Field::SCORED {
+            let document = match field {
+                Field::Url | Field::Site => url,
+                Field::Title => title,
+                Field::Headings => headings,
+                Field::Highlighted => highlighted,
+                Field::Content => content,
+            };
+            let words = inverted_index::main::get_field_words(field, document, language);
+            let len = words.len() as u64;
+            match field {
+                Field::Url => field_lengths.url = len,
+                Field::Title => field_lengths.title = len,
+                Field::Headings => field_lengths.headings = len,
+                Field::Highlighted => field_lengths.highlighted = len,
+                Field::Content => field_lengths.content = len,
+                Field::Site => (),
+            }
+            if !words.is_empty() {
+                field_terms.insert(field, words.into_iter().collect());
+            }
+        }
+        DocStats {
+            field_lengths,
+            field_terms,
+        }
+    }
+
+    // a re-crawl swaps the old contribution for the new one under a single lock,
+    // so readers never see the document counted twice or not at all
+    pub fn update(old_doc: Option<&DocStats>, new_doc: Option<&DocStats>) {
+        let mut corpus_stats = CORPUS_STATS.write().unwrap();
+        if let Some(old_doc) = old_doc {
+            corpus_stats.remove(old_doc);
+        }
+        if let Some(new_doc) = new_doc {
+            corpus_stats.add(new_doc);
+        }
+    }
+
+    pub fn get_snapshot(terms: &[&str]) -> CorpusStats {
+        CORPUS_STATS.read().unwrap().get_snapshot(terms)
+    }
+
+    pub fn get_total_count() -> u64 {
+        CORPUS_STATS.read().unwrap().total_count
+    }
+
+    pub fn get_summary() -> StatsSummary {
+        let corpus_stats = CORPUS_STATS.read().unwrap();
+        StatsSummary {
+            total_count: corpus_stats.total_count,
+            term_count: corpus_stats.terms.len(),
+            field_count: corpus_stats.field_count,
+            avg_field_len: AvgFieldLen {
+                url: corpus_stats.get_avg_field_len(Field::Url),
+                title: corpus_stats.get_avg_field_len(Field::Title),
+                headings: corpus_stats.get_avg_field_len(Field::Headings),
+                highlighted: corpus_stats.get_avg_field_len(Field::Highlighted),
+                content: corpus_stats.get_avg_field_len(Field::Content),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn doc_freq_and_averages_follow_add_and_remove() {
+        let mut corpus_stats = CorpusStats::default();
+        assert_eq!(corpus_stats.get_avg_field_len(Field::Content), 0.0);
+
+        let first = main::get_doc_stats(
+            "https://stats.example/rust",
+            "rust rust memory",
+            "rust",
+            "",
+            "",
+            Language::English,
+        );
+        let second = main::get_doc_stats(
+            "https://stats.example/python",
+            "python memory",
+            "python",
+            "",
+            "",
+            Language::English,
+        );
+        corpus_stats.add(&first);
+        corpus_stats.add(&second);
+        assert_eq!(corpus_stats.total_count, 2);
+        // repeated in title and content, still one document
+        assert_eq!(corpus_stats.get_doc_freq("rust", Option::None), 1);
+        assert_eq!(corpus_stats.get_doc_freq("rust", Some(Field::Title)), 1);
+        assert_eq!(corpus_stats.get_doc_freq("memori", Option::None), 2);
+        assert_eq!(corpus_stats.get_doc_freq("memori", Some(Field::Title)), 0);
+        assert_eq!(corpus_stats.get_avg_field_len(Field::Content), 2.5);
+
+        corpus_stats.remove(&first);
+        assert_eq!(corpus_stats.total_count, 1);
+        assert_eq!(corpus_stats.get_doc_freq("rust", Option::None), 0);
+        assert!(!corpus_stats.terms.contains_key("rust"));
+        assert_eq!(corpus_stats.get_doc_freq("memori", Option::None), 1);
+        assert_eq!(corpus_stats.get_avg_field_len(Field::Content), 2.0);
+
+        corpus_stats.remove(&second);
+        assert!(corpus_stats.terms.is_empty());
+        assert_eq!(corpus_stats.get_avg_field_len(Field::Content), 0.0);
+    }
+}
@@ -0,0 +1,158 @@
+use crate::analyzer;
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::{Arc, RwLock};
+use std::{env, fs};
+
+#[derive(Default)]
+pub struct SynonymMap {
+    // normalized phrase => phrases it expands to
+    entries: HashMap<String, Vec<String>>,
+    max_phrase_len: usize,
+}
+
+pub struct SynonymMatch {
+    pub start: usize,
+    pub phrases: Vec<String>,
+}
+
+lazy_static! {
+    static ref SYNONYMS: Arc<RwLock<SynonymMap>> = Arc::new(RwLock::new(SynonymMap::default()));
+}
+
+impl SynonymMap {
+    fn add(&mut self, from: &str, to: &str) {
+        if from == to {
+            return;
+        }
+        self.max_phrase_len = self.max_phrase_len.max(from.split(' ').count());
+        let phrases = self.entries.entry(from.to_string()).or_default();
+        if !phrases.iter().any(|phrase| phrase == to) {
+            phrases.push(to.to_string());
+        }
+    }
+
+    // longest match first over normalized query words, matches never overlap
+    pub fn get_matches(&self, words: &[String]) -> Vec<SynonymMatch> {
+        let mut matches = Vec::new();
+        let mut start = 0;
+        while start < words.len() {
+            let max_len = self.max_phrase_len.min(words.len() - start);
+            let found = (1..=max_len).rev().find_map(|len| {
+                let phrase = words[start..start + len].join(" ");
+                self.entries.get(&phrase).map(|phrases| (len, phrases.clone()))
+            });
+            match found {
+                Some((len, phrases)) => {
+                    matches.push(SynonymMatch { start, phrases });
+                    start += len;
+                }
+                None => start += 1,
+            }
+        }
+        matches
+    }
+}
+
+pub mod main {
+    use super::*;
+
+    pub fn normalize_phrase(phrase: &str) -> String {
+        phrase
+            .split_whitespace()
+            .map(|word| analyzer::main::normalize(word.trim_matches(|c: char| !c.is_alphanumeric())))
+            .filter(|word| !word.is_empty())
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+
+    // `k8s, kubernetes` is an equivalent set, `js => javascript` a one-way mapping
+    pub fn parse(file_data: &str) -> SynonymMap {
+        let mut synonyms = SynonymMap::default();
+        for line in file_data.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let split_phrases = |phrases: &str| {
+                phrases
+                    .split(',')
+                    .map(normalize_phrase)
+                    .filter(|phrase| !phrase.is_empty())
+                    .collect::<Vec<String>>()
+            };
+            match line.split_once("=>") {
+                Some((from, to)) => {
+                    let to = split_phrases(to);
+                    for from in split_phrases(from) {
+                        for to in to.iter() {
+                            synonyms.add(&from, to);
+                        }
+                    }
+                }
+                None => {
+                    let phrases = split_phrases(line);
+                    for from in phrases.iter() {
+                        for to in phrases.iter() {
+                            synonyms.add(from, to);
+                        }
+                    }
+                }
+            }
+        }
+        synonyms
+    }
+
+    pub fn load() -> Result<usize, Box<dyn Error + Send + Sync>> {
+        let filepath = &env::var("SYNONYMS_FILE_PATH")?;
+        let file_data = fs::read_to_string(filepath)?;
+        let synonyms = parse(&file_data);
+        let count = synonyms.entries.len();
+        *SYNONYMS.write().unwrap() = synonyms;
+        println!("=== SYNONYMS LOADED : {count} ===");
+        Ok(count)
+    }
+
+    pub fn get_weight() -> f64 {
+        env::var("SYNONYM_TERM_WEIGHT")
+            .unwrap_or(String::from("0.8"))
+            .parse::<f64>()
+            .unwrap()
+    }
+
+    pub fn get_matches(words: &[String]) -> Vec<SynonymMatch> {
+        SYNONYMS.read().unwrap().get_matches(words)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_words(text: &str) -> Vec<String> {
+        text.split_whitespace().map(main::normalize_phrase).collect()
+    }
+
+    #[test]
+    fn equivalent_sets_and_one_way_mappings_expand_the_longest_phrase() {
+        let synonyms = main::parse("# comment\nk8s, Kubernetes\njs => javascript\nnew york, nyc\n\nnew => fresh");
+        let matches = synonyms.get_matches(&get_words("K8S on new york with js"));
+        let matches = matches
+            .into_iter()
+            .map(|synonym_match| (synonym_match.start, synonym_match.phrases))
+            .collect::<Vec<(usize, Vec<String>)>>();
+        assert_eq!(
+            matches,
+            vec![
+                (0, vec![String::from("kubernetes")]),
+                (2, vec![String::from("nyc")]),
+                (5, vec![String::from("javascript")]),
+            ]
+        );
+        assert_eq!(synonyms.get_matches(&get_words("kubernetes"))[0].phrases, vec!["k8s"]);
+        // one way only
+        assert!(synonyms.get_matches(&get_words("javascript")).is_empty());
+        assert_eq!(synonyms.get_matches(&get_words("new"))[0].phrases, vec!["fresh"]);
+    }
+}
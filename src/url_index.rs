@@ -1,10 +1,18 @@
+use crate::index_file::DocEntry;
+use crate::inverted_index;
+use crate::language::{self, Language};
+use crate::recency;
+use crate::segment;
+use crate::stats::{self, DocStats};
+use crate::wal::{self, Record, WAL};
 use chrono::{self, DateTime, Utc};
 use lazy_static::lazy_static;
-use md5;
+use serde::Serialize;
+use std::collections::HashMap;
 use std::error::Error;
 use std::fs::File;
-use std::io::Write;
-use std::io::{BufRead, BufReader};
+use std::io::{self, Write};
+use std::io::{BufRead, BufReader, BufWriter};
 use std::sync::{Arc, RwLock};
 use std::{env, fs};
 
@@ -16,11 +24,162 @@ pub struct Node {
     pub headings: String,
     pub highlighted: String,
     pub content: String,
+    pub language: Language,
+    // publication date from the page, its last modification or the Last-Modified header
+    pub published: Option<DateTime<Utc>>,
+    // from the Content-Type header without its parameters, empty when unknown
+    pub mime_type: String,
     left: Box<Option<Node>>,
     right: Box<Option<Node>>,
     pub timestamp: DateTime<Utc>,
 }
 
+// what filters, sorting, facets and scoring need from a document, without its text. host
+// and mime_type are ids into the values interned by MetaColumns
+#[derive(Clone, Copy, Debug)]
+pub struct DocMeta {
+    pub language: Language,
+    pub timestamp: DateTime<Utc>,
+    pub published: Option<DateTime<Utc>>,
+    pub host: u32,
+    pub mime_type: u32,
+    // token counts per field, the same analysis the inverted index uses
+    pub field_lengths: FieldCount,
+}
+
+// the DocMeta of every document stored as columns, a row per doc id of the segments so a
+// posting is looked up without its url. rows of deleted urls are reused, interned values are
+// never dropped, there are few distinct hosts and mime types
+#[derive(Default)]
+pub struct MetaColumns {
+    rows: HashMap<u32, usize>,
+    free_rows: Vec<usize>,
+    languages: Vec<Language>,
+    timestamps: Vec<DateTime<Utc>>,
+    published: Vec<Option<DateTime<Utc>>>,
+    hosts: Vec<u32>,
+    mime_types: Vec<u32>,
+    field_lengths: Vec<FieldCount>,
+    values: Vec<String>,
+    value_ids: HashMap<String, u32>,
+    // documents per language, the candidates a query without a language is analyzed in
+    language_counts: HashMap<Language, usize>,
+}
+
+impl MetaColumns {
+    fn intern(&mut self, value: &str) -> u32 {
+        if let Some(id) = self.value_ids.get(value) {
+            return *id;
+        }
+        let id = self.values.len() as u32;
+        self.values.push(value.to_string());
+        self.value_ids.insert(value.to_string(), id);
+        id
+    }
+
+    pub fn get_id(&self, value: &str) -> Option<u32> {
+        self.value_ids.get(value).copied()
+    }
+
+    pub fn get_value(&self, id: u32) -> &str {
+        &self.values[id as usize]
+    }
+
+    pub fn get(&self, url: &str) -> Option<DocMeta> {
+        self.get_by_doc_id(segment::main::get_doc_id(url)?)
+    }
+
+    pub fn get_by_doc_id(&self, doc_id: u32) -> Option<DocMeta> {
+        let row = *self.rows.get(&doc_id)?;
+        Some(DocMeta {
+            language: self.languages[row],
+            timestamp: self.timestamps[row],
+            published: self.published[row],
+            host: self.hosts[row],
+            mime_type: self.mime_types[row],
+            field_lengths: self.field_lengths[row],
+        })
+    }
+
+    pub fn set(&mut self, node: &Node, field_lengths: FieldCount) {
+        let host = self.intern(&main::get_host(&node.url));
+        let mime_type = self.intern(&node.mime_type);
+        let doc_id = segment::main::add_doc_id(&node.url);
+        if let Some(row) = self.rows.get(&doc_id) {
+            self.remove_language(self.languages[*row]);
+        }
+        *self.language_counts.entry(node.language).or_insert(0) += 1;
+        let row = match self.rows.get(&doc_id).copied().or_else(|| self.free_rows.pop()) {
+            Some(row) => row,
+            None => {
+                self.languages.push(node.language);
+                self.timestamps.push(node.timestamp);
+                self.published.push(node.published);
+                self.hosts.push(host);
+                self.mime_types.push(mime_type);
+                self.field_lengths.push(field_lengths);
+                self.languages.len() - 1
+            }
+        };
+        self.languages[row] = node.language;
+        self.timestamps[row] = node.timestamp;
+        self.published[row] = node.published;
+        self.hosts[row] = host;
+        self.mime_types[row] = mime_type;
+        self.field_lengths[row] = field_lengths;
+        self.rows.insert(doc_id, row);
+    }
+
+    pub fn set_field_lengths(&mut self, url: &str, field_lengths: FieldCount) {
+        let row = segment::main::get_doc_id(url).and_then(|doc_id| self.rows.get(&doc_id));
+        if let Some(row) = row {
+            self.field_lengths[*row] = field_lengths;
+        }
+    }
+
+    // (documents, summed field lengths) of every row
+    pub fn get_field_count(&self) -> (u64, FieldCount) {
+        let mut field_count = FieldCount::default();
+        for row in self.rows.values() {
+            field_count.add(&self.field_lengths[*row]);
+        }
+        (self.rows.len() as u64, field_count)
+    }
+
+    pub fn remove(&mut self, url: &str) {
+        let Some(doc_id) = segment::main::get_doc_id(url) else {
+            return;
+        };
+        if let Some(row) = self.rows.remove(&doc_id) {
+            self.remove_language(self.languages[row]);
+            self.free_rows.push(row);
+        }
+    }
+
+    fn remove_language(&mut self, language: Language) {
+        if let Some(count) = self.language_counts.get_mut(&language) {
+            *count -= 1;
+            if *count == 0 {
+                self.language_counts.remove(&language);
+            }
+        }
+    }
+
+    pub fn get_languages(&self) -> Vec<Language> {
+        let mut languages = self.language_counts.keys().copied().collect::<Vec<Language>>();
+        languages.sort_by_key(|language| language.code());
+        languages
+    }
+}
+
+impl DocMeta {
+    // the crawl time stands in for pages without a date of their own
+    pub fn get_date(&self) -> DateTime<Utc> {
+        self.published.unwrap_or(self.timestamp)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Serialize)]
 pub struct FieldCount {
     pub url: u64,
     pub title: u64,
@@ -29,59 +188,107 @@ pub struct FieldCount {
     pub content: u64,
 }
 
-pub struct IndexConfig {
-    pub total_count: u64,
-    pub field_count: FieldCount,
-}
+// tests share the global indexes, the ones asserting on CORPUS_STATS hold this
+#[cfg(test)]
+pub static TEST_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
 
 lazy_static! {
     static ref root: Arc<RwLock<Option<Node>>> = Arc::new(RwLock::new(Option::None));
-    pub static ref INDEX_CONFIG: Arc<RwLock<IndexConfig>> = Arc::new(RwLock::new(IndexConfig {
-        total_count: 0,
-        field_count: FieldCount {
-            url: 0,
-            title: 0,
-            headings: 0,
-            highlighted: 0,
-            content: 0,
-        }
-    }));
+    pub static ref META_COLUMNS: RwLock<MetaColumns> = RwLock::new(MetaColumns::default());
 }
 
 pub mod main {
     use super::*;
-    pub fn index() -> Result<(), Box<dyn Error>> {
-        let filepath = &env::var("URL_INDEX_FILE_PATH")?;
-        let file_data = File::open(filepath);
-        if file_data.is_err() {
-            println!("err while loading index : {:?}", file_data);
-            return Ok(());
+    // the columns of a saved line or a logged insert
+    fn get_loaded_node(columns: &[&str]) -> Option<Node> {
+        match columns.len() {
+            5..=8 => (),
+            _ => return Option::None,
         }
-        let file_data = file_data.unwrap();
-        let reader = BufReader::new(file_data);
-        for line in reader.lines() {
-            if line.is_err() {
-                println!("buffer read line error : {:?}", line);
-                continue;
+        let [url, title, headings, highlighted, content]: [&str; 5] = columns[..5].try_into().unwrap();
+        // lines written before the language column existed are classified again
+        let language = match columns.get(5) {
+            Some(code) => Language::from_code(code),
+            None => language::main::detect(content),
+        };
+        let published = columns
+            .get(6)
+            .and_then(|date| recency::main::parse_date(date));
+        let mime_type = columns.get(7).copied().unwrap_or_default();
+        // field lengths and corpus stats are filled in by the inverted index load
+        let mut node = new_node(url, content, title, headings, highlighted, language);
+        node.published = published;
+        node.mime_type = mime_type.to_string();
+        Some(node)
+    }
+
+    fn load_node(node: Node) {
+        let mut root_ref = root.write().unwrap();
+        META_COLUMNS.write().unwrap().set(&node, FieldCount::default());
+        insert_helper(&mut root_ref, node);
+    }
+
+    fn replay(record: Record) {
+        match record {
+            Record::Insert {
+                url,
+                title,
+                headings,
+                highlighted,
+                content,
+                language,
+                published,
+                mime_type,
+            } => {
+                let columns = [&url, &title, &headings, &highlighted, &content, &language, &published, &mime_type];
+                if let Some(node) = get_loaded_node(&columns.map(String::as_str)) {
+                    load_node(node);
+                }
+            }
+            Record::Delete { url } => {
+                let mut root_ref = root.write().unwrap();
+                if delete_helper(&mut root_ref, &url).is_some() {
+                    META_COLUMNS.write().unwrap().remove(&url);
+                }
             }
-            let content = line.unwrap();
-            let content_data = content.split("$$==$$=$$").collect::<Vec<&str>>();
-            match content_data.len() {
-                5 => (),
-                _ => continue,
+        }
+    }
+
+    // the saved snapshot, then whatever was logged after it
+    pub fn index() -> Result<(), Box<dyn Error>> {
+        load_file(&env::var("URL_INDEX_FILE_PATH")?)
+    }
+
+    pub fn load_file(filepath: &str) -> Result<(), Box<dyn Error>> {
+        match File::open(filepath) {
+            Ok(file_data) => {
+                let reader = BufReader::new(file_data);
+                for line in reader.lines() {
+                    if line.is_err() {
+                        println!("buffer read line error : {:?}", line);
+                        continue;
+                    }
+                    let content = line.unwrap();
+                    let columns = content.split("$$==$$=$$").collect::<Vec<&str>>();
+                    if let Some(node) = get_loaded_node(&columns) {
+                        load_node(node);
+                    }
+                }
             }
-            let [url, title, headings, highlighted, content]: [&str; 5] =
-                content_data[..5].try_into().unwrap();
-            insert(url, content, title, headings, highlighted);
+            Err(err) => println!("err while loading index : {:?}", err),
+        }
+        let replayed = wal::main::replay(&WAL.lock().unwrap(), replay)?;
+        println!("=== URL INDEXING FINISHED === replayed : {replayed}");
+        // a checkpoint keeps the next start from replaying the same records
+        if replayed > 0
+            && let Err(err) = write_file(filepath)
+        {
+            println!("err while writing index : {:?}", err);
         }
-        println!("=== URL INDEXING FINISHED ===");
         Ok(())
     }
 
-    fn traverse_and_write(
-        node: &Option<Node>,
-        mut file: &File,
-    ) -> Result<(), Box<dyn Error + Send>> {
+    fn traverse_and_write(node: &Option<Node>, file: &mut impl Write) -> io::Result<()> {
         if node.is_none() {
             return Ok(());
         }
@@ -91,150 +298,424 @@ pub mod main {
         let title = &node.title;
         let headings = &node.headings;
         let highlighted = &node.highlighted;
+        let published = node.published.map(|date| date.to_rfc3339()).unwrap_or_default();
         let write_content = format!(
-            "{}$$==$$=$${}$$==$$=$${}$$==$$=$${}$$==$$=$${}\n",
-            url, title, headings, highlighted, content
+            "{}$$==$$=$${}$$==$$=$${}$$==$$=$${}$$==$$=$${}$$==$$=$${}$$==$$=$${}$$==$$=$${}\n",
+            url,
+            title,
+            headings,
+            highlighted,
+            content,
+            node.language.code(),
+            published,
+            node.mime_type
         );
-        let _ = file.write(write_content.as_bytes());
-        let _ = traverse_and_write(&node.right, &file);
-        let _ = traverse_and_write(&node.left, &file);
-        Ok(())
+        file.write_all(write_content.as_bytes())?;
+        traverse_and_write(&node.right, file)?;
+        traverse_and_write(&node.left, file)
     }
 
+    // a checkpoint: the snapshot is synced and renamed into place before the log it
+    // covers is truncated, mutations wait on the log lock meanwhile
     pub fn write_to_file() -> Result<(), Box<dyn Error + Send + Sync>> {
+        write_file(&env::var("URL_INDEX_FILE_PATH")?)
+    }
+
+    fn write_file(filepath: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
         println!("writing index to file");
-        let filepath = &env::var("URL_INDEX_FILE_PATH")?;
-        let temp_filepath = String::from(filepath).replace(".txt", "-temp.txt");
-        let file_data = File::create(&temp_filepath)?;
-        // let root_clone = root.clone();
+        let temp_filepath = format!("{filepath}.temp");
+        let mut wal = WAL.lock().unwrap();
+        let mut file_data = BufWriter::new(File::create(&temp_filepath)?);
         let root_ref = root.read().unwrap();
-        let _ = traverse_and_write(&root_ref, &file_data);
-        let _ = fs::rename(&temp_filepath, filepath);
+        traverse_and_write(&root_ref, &mut file_data)?;
         drop(root_ref);
-        // drop(root_clone);
+        file_data.flush()?;
+        file_data.get_ref().sync_all()?;
+        drop(file_data);
+        fs::rename(&temp_filepath, filepath)?;
+        // the rename has to be durable before the log it replaces is dropped
+        wal::main::sync_parent_dir(filepath)?;
+        wal.truncate()?;
         Ok(())
     }
 
+    pub fn get_host(url: &str) -> String {
+        let url = url.split("://").nth(1).unwrap_or(url);
+        let host = url.split(['/', '?', '#']).next().unwrap_or("");
+        let host = host.rsplit('@').next().unwrap_or(host);
+        let host = host.split(':').next().unwrap_or(host).to_lowercase();
+        host.strip_prefix("www.").unwrap_or(&host).to_string()
+    }
+
     pub fn get_hash(content: &str) -> String {
-        let hash = md5::compute(content)
+        md5::compute(content)
             .iter()
             .map(|x| x.to_string())
-            .collect();
-        return hash;
-    }
-
-    pub fn calc_helper(old_value: u64, curr_value: u64, count: u64) -> u64 {
-        old_value * ((count - 1) / count) + (curr_value / count)
+            .collect()
     }
 
-    pub fn handle_index_config_update(
+    fn new_node(
         url: &str,
         content: &str,
         title: &str,
         headings: &str,
         highlighted: &str,
-    ) {
-        let mut curr_index_config = INDEX_CONFIG.write().unwrap();
-        curr_index_config.total_count += 1;
-        curr_index_config.field_count.url += url.len() as u64;
-        curr_index_config.field_count.content += content.len() as u64;
-        curr_index_config.field_count.title += title.len() as u64;
-        curr_index_config.field_count.headings += headings.len() as u64;
-        curr_index_config.field_count.highlighted += highlighted.len() as u64;
-    }
-
-    fn insert_helper(
-        node: &mut Option<Node>,
-        url: &str,
-        content: &str,
-        title: &str,
-        headings: &str,
-        highlighted: &str,
-    ) -> Option<Node> {
+        language: Language,
+    ) -> Node {
+        Node {
+            url: String::from(url),
+            hash: get_hash(content),
+            content: String::from(content),
+            title: String::from(title),
+            headings: String::from(headings),
+            highlighted: String::from(highlighted),
+            language,
+            published: Option::None,
+            mime_type: String::new(),
+            left: Box::new(Option::None),
+            right: Box::new(Option::None),
+            timestamp: chrono::Utc::now(),
+        }
+    }
+
+    // returns the replaced node when the url was already indexed
+    fn insert_helper(node: &mut Option<Node>, mut new_node: Node) -> Option<Node> {
         if node.is_none() {
-            let hash = get_hash(content);
-            let new_node = Some(Node {
-                url: String::from(url),
-                hash: hash,
-                content: String::from(content),
-                title: String::from(title),
-                headings: String::from(headings),
-                highlighted: String::from(highlighted),
-                left: Box::new(Option::None),
-                right: Box::new(Option::None),
-                timestamp: chrono::Utc::now(),
-            });
-            return new_node;
-        }
-        let node = node.as_mut().unwrap();
-        if node.url == url {
-            node.title = String::from(title);
-            node.headings = String::from(headings);
-            node.highlighted = String::from(highlighted);
-            node.hash = get_hash(content);
-            node.timestamp = chrono::Utc::now();
+            *node = Some(new_node);
             return Option::None;
-        } else if *url >= *node.url {
-            let resp = insert_helper(&mut node.right, url, content, title, headings, highlighted);
-            match resp {
-                Some(next_node) => {
-                    *node.right = Option::Some(next_node);
-                    return Option::None;
-                }
-                _ => return Option::None,
-            }
+        }
+        let curr_node = node.as_mut().unwrap();
+        if curr_node.url == new_node.url {
+            // children stay in place, every other field is replaced
+            std::mem::swap(&mut curr_node.left, &mut new_node.left);
+            std::mem::swap(&mut curr_node.right, &mut new_node.right);
+            Some(std::mem::replace(curr_node, new_node))
+        } else if *new_node.url >= *curr_node.url {
+            insert_helper(&mut curr_node.right, new_node)
         } else {
-            let resp = insert_helper(&mut node.left, url, content, title, headings, highlighted);
-            match resp {
-                Some(next_node) => {
-                    *node.left = Option::Some(next_node);
-                    return Option::None;
-                }
-                _ => return Option::None,
-            }
+            insert_helper(&mut curr_node.left, new_node)
         }
     }
 
-    pub fn insert(url: &str, content: &str, title: &str, headings: &str, highlighted: &str) {
+    #[allow(clippy::too_many_arguments)]
+    pub fn insert(
+        url: &str,
+        content: &str,
+        title: &str,
+        headings: &str,
+        highlighted: &str,
+        language: Language,
+        published: Option<DateTime<Utc>>,
+        mime_type: &str,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
         println!("url_index insert triggered => url : {url}");
+        let mut new_node = new_node(url, content, title, headings, highlighted, language);
+        new_node.published = published;
+        new_node.mime_type = mime_type.to_string();
+        let new_doc_stats = stats::main::get_doc_stats(url, content, title, headings, highlighted, language);
+        // logged before it is applied, a crash after this point replays it
+        let mut wal = WAL.lock().unwrap();
+        wal.append(&Record::Insert {
+            url: url.to_string(),
+            title: title.to_string(),
+            headings: headings.to_string(),
+            highlighted: highlighted.to_string(),
+            content: content.to_string(),
+            language: language.code().to_string(),
+            published: published.map(|date| date.to_rfc3339()).unwrap_or_default(),
+            mime_type: mime_type.to_string(),
+        })?;
         let mut root_ref = root.write().unwrap();
-        if root_ref.is_none() {
-            let hash = get_hash(content);
-            *root_ref = Some(Node {
-                url: String::from(url),
-                hash: hash,
-                content: String::from(content),
-                title: String::from(title),
-                headings: String::from(headings),
-                highlighted: String::from(highlighted),
-                left: Box::new(Option::None),
-                right: Box::new(Option::None),
-                timestamp: chrono::Utc::now(),
-            });
-            println!("root is updated");
-            handle_index_config_update(url, content, title, headings, highlighted);
+        META_COLUMNS.write().unwrap().set(&new_node, new_doc_stats.field_lengths);
+        let old_node = insert_helper(&mut root_ref, new_node);
+        // stats and columns are updated before the tree is released so all of them always
+        // describe the same documents
+        let old_doc_stats = old_node.as_ref().map(get_doc_stats);
+        stats::main::update(old_doc_stats.as_ref(), Some(&new_doc_stats));
+        drop(wal);
+        Ok(())
+    }
+
+    fn traverse_helper(node: &Option<Node>, visit: &mut impl FnMut(&Node)) {
+        let Some(node) = node.as_ref() else {
+            return;
+        };
+        visit(node);
+        traverse_helper(&node.left, visit);
+        traverse_helper(&node.right, visit);
+    }
+
+    // (url, content hash) of every document
+    pub fn get_doc_hashes() -> Vec<(String, String)> {
+        let mut docs = Vec::new();
+        let root_ref = root.read().unwrap();
+        traverse_helper(&root_ref, &mut |node| docs.push((node.url.to_string(), node.hash.to_string())));
+        docs
+    }
+
+    pub fn get_languages() -> Vec<Language> {
+        META_COLUMNS.read().unwrap().get_languages()
+    }
+
+    pub fn get_doc_entries() -> Vec<DocEntry> {
+        let mut docs = Vec::new();
+        let root_ref = root.read().unwrap();
+        let meta_columns = META_COLUMNS.read().unwrap();
+        traverse_helper(&root_ref, &mut |node| {
+            docs.push(DocEntry {
+                url: node.url.to_string(),
+                hash: node.hash.to_string(),
+                field_lengths: meta_columns.get(&node.url).map(|meta| meta.field_lengths).unwrap_or_default(),
+            })
+        });
+        docs
+    }
+
+    pub fn set_field_lengths(field_lengths: HashMap<String, FieldCount>) {
+        let mut meta_columns = META_COLUMNS.write().unwrap();
+        for (url, lengths) in field_lengths {
+            meta_columns.set_field_lengths(&url, lengths);
+        }
+    }
+
+    // corpus stats from the stored field lengths and the postings, a load doesn't analyze
+    // every document again to get them. the tree stays locked so no insert is counted half
+    pub fn rebuild_stats() {
+        let _root_ref = root.write().unwrap();
+        let (total_count, field_count) = META_COLUMNS.read().unwrap().get_field_count();
+        stats::main::set(total_count, field_count, inverted_index::main::get_term_stats());
+    }
+
+    fn get_doc_stats(node: &Node) -> DocStats {
+        stats::main::get_doc_stats(
+            &node.url,
+            &node.content,
+            &node.title,
+            &node.headings,
+            &node.highlighted,
+            node.language,
+        )
+    }
+
+    fn title_helper(node: &Option<Node>, prefix: &str, titles: &mut HashMap<String, usize>) {
+        if node.is_none() {
             return;
         }
-        insert_helper(&mut root_ref, url, content, title, headings, highlighted);
-        handle_index_config_update(url, content, title, headings, highlighted);
+        let node = node.as_ref().unwrap();
+        if !node.title.is_empty() && node.title.to_lowercase().starts_with(prefix) {
+            *titles.entry(node.title.to_string()).or_insert(0) += 1;
+        }
+        title_helper(&node.left, prefix, titles);
+        title_helper(&node.right, prefix, titles);
+    }
+
+    // titles starting with prefix, ranked by how many documents carry that title
+    pub fn get_title_completions(prefix: &str, limit: usize) -> Vec<(String, usize)> {
+        let prefix = prefix.to_lowercase();
+        let mut titles = HashMap::new();
+        let root_ref = root.read().unwrap();
+        title_helper(&root_ref, &prefix, &mut titles);
+        drop(root_ref);
+        let mut titles = titles.into_iter().collect::<Vec<(String, usize)>>();
+        titles.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        titles.truncate(limit);
+        titles
     }
 
-    fn get_helper(node: &Option<Node>, url: &str) -> Option<Node> {
+    fn get_helper<'a>(node: &'a Option<Node>, url: &str) -> Option<&'a Node> {
         if node.is_none() {
             return Option::None;
         }
         let node = node.as_ref().unwrap();
         if node.url == url {
-            return Option::Some(node.clone());
+            Option::Some(node)
         } else if *url >= *node.url {
-            return get_helper(&node.right, url);
+            get_helper(&node.right, url)
         } else {
-            return get_helper(&node.left, url);
+            get_helper(&node.left, url)
         }
     }
 
     pub fn get_by_url(url: &str) -> Option<Node> {
+        get_with(url, Node::clone)
+    }
+
+    // reads the stored document in place, only what read returns is copied out
+    pub fn get_with<T>(url: &str, read: impl FnOnce(&Node) -> T) -> Option<T> {
         let root_ref = root.read().unwrap();
-        return get_helper(&root_ref, url);
+        get_helper(&root_ref, url).map(read)
+    }
+
+    // lookup without touching the stored document
+    pub fn get_meta(url: &str) -> Option<DocMeta> {
+        META_COLUMNS.read().unwrap().get(url)
+    }
+
+    pub fn get_meta_by_doc_id(doc_id: u32) -> Option<DocMeta> {
+        META_COLUMNS.read().unwrap().get_by_doc_id(doc_id)
+    }
+
+    // detaches the smallest node of a subtree, its right child takes its place
+    fn take_min(node: &mut Option<Node>) -> Option<Node> {
+        if node.as_ref()?.left.is_some() {
+            return take_min(&mut node.as_mut().unwrap().left);
+        }
+        let mut min_node = node.take()?;
+        *node = min_node.right.take();
+        Some(min_node)
+    }
+
+    fn delete_helper(node: &mut Option<Node>, url: &str) -> Option<Node> {
+        let curr_node = node.as_mut()?;
+        if curr_node.url != url {
+            if *url >= *curr_node.url {
+                return delete_helper(&mut curr_node.right, url);
+            }
+            return delete_helper(&mut curr_node.left, url);
+        }
+        let mut deleted_node = node.take().unwrap();
+        let left = deleted_node.left.take();
+        let right = deleted_node.right.take();
+        *node = match (left, right) {
+            (Option::None, right) => right,
+            (left, Option::None) => left,
+            (left, right) => {
+                let mut right = right;
+                let mut successor = take_min(&mut right).unwrap();
+                *successor.left = left;
+                *successor.right = right;
+                Some(successor)
+            }
+        };
+        Some(deleted_node)
+    }
+
+    pub fn delete(url: &str) -> Result<Option<Node>, Box<dyn Error + Send + Sync>> {
+        println!("url_index delete triggered => url : {url}");
+        let mut wal = WAL.lock().unwrap();
+        if get_meta(url).is_none() {
+            return Ok(Option::None);
+        }
+        wal.append(&Record::Delete { url: url.to_string() })?;
+        let mut root_ref = root.write().unwrap();
+        let deleted_node = delete_helper(&mut root_ref, url);
+        if let Some(node) = &deleted_node {
+            META_COLUMNS.write().unwrap().remove(url);
+            stats::main::update(Some(&get_doc_stats(node)), Option::None);
+        }
+        Ok(deleted_node)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_index_config() -> (u64, u64, u64) {
+        let corpus_stats = stats::CORPUS_STATS.read().unwrap();
+        (
+            corpus_stats.total_count,
+            corpus_stats.field_count.title,
+            corpus_stats.field_count.content,
+        )
+    }
+
+    #[test]
+    fn insert_update_delete_keeps_index_config() {
+        let _lock = TEST_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+        let url = "https://url-index-test.example/upsert";
+        let (count, title_len, content_len) = get_index_config();
+
+        main::insert(url, "first content", "first", "", "", Language::English, Option::None, "text/html").unwrap();
+        assert_eq!(get_index_config(), (count + 1, title_len + 1, content_len + 2));
+        let meta = main::get_meta(url).unwrap();
+        assert_eq!(META_COLUMNS.read().unwrap().get_value(meta.mime_type), "text/html");
+        assert_eq!(META_COLUMNS.read().unwrap().get_value(meta.host), "url-index-test.example");
+
+        main::insert(url, "second, longer content", "second", "h", "b", Language::French, Option::None, "").unwrap();
+        let node = main::get_by_url(url).unwrap();
+        assert_eq!(node.content, "second, longer content");
+        assert_eq!(node.title, "second");
+        assert_eq!(node.headings, "h");
+        assert_eq!(node.highlighted, "b");
+        assert_eq!(node.language, Language::French);
+        assert_eq!(node.hash, main::get_hash("second, longer content"));
+        assert_eq!(get_index_config(), (count + 1, title_len + 1, content_len + 3));
+
+        main::insert(url, "second, longer content", "second", "h", "b", Language::French, Option::None, "").unwrap();
+        assert_eq!(get_index_config(), (count + 1, title_len + 1, content_len + 3));
+
+        assert!(main::delete(url).unwrap().is_some());
+        assert!(main::get_by_url(url).is_none());
+        assert!(main::get_meta(url).is_none());
+        assert_eq!(get_index_config(), (count, title_len, content_len));
+
+        assert!(main::delete(url).unwrap().is_none());
+        assert_eq!(get_index_config(), (count, title_len, content_len));
+    }
+
+    #[test]
+    fn delete_keeps_other_urls_reachable() {
+        let _lock = TEST_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+        let urls = (0..16)
+            .map(|idx| format!("https://url-index-test.example/tree/{:02}", (idx * 7) % 16))
+            .collect::<Vec<String>>();
+        for url in urls.iter() {
+            main::insert(url, "content", "title", "", "", Language::English, Option::None, "").unwrap();
+        }
+        let deleted_urls = [&urls[0], &urls[5], &urls[8], &urls[15]];
+        for url in deleted_urls {
+            assert!(main::delete(url).unwrap().is_some());
+        }
+        for url in urls.iter() {
+            assert_eq!(main::get_by_url(url).is_some(), !deleted_urls.contains(&url));
+        }
+        for url in urls.iter().filter(|url| !deleted_urls.contains(url)) {
+            assert!(main::delete(url).unwrap().is_some());
+        }
+    }
+
+    #[test]
+    fn logged_mutations_are_replayed_on_start_and_checkpointed() {
+        let _lock = TEST_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+        let dir = env::temp_dir().join(format!("search-engine-url-index-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let filepath = dir.join("index.txt").to_string_lossy().to_string();
+        let wal_filepath = format!("{filepath}.wal");
+        let kept_url = "https://url-index-test.example/wal/kept";
+        let deleted_url = "https://url-index-test.example/wal/deleted";
+
+        let disabled_wal = std::mem::replace(
+            &mut *WAL.lock().unwrap(),
+            wal::Wal::new(wal_filepath.clone(), wal::FsyncPolicy::Always),
+        );
+        main::insert(kept_url, "kept content", "kept", "", "", Language::English, Option::None, "text/html").unwrap();
+        main::insert(deleted_url, "deleted content", "deleted", "", "", Language::English, Option::None, "").unwrap();
+        main::delete(deleted_url).unwrap();
+        assert!(fs::metadata(&wal_filepath).unwrap().len() > 0);
+
+        // a crash loses the tree but not the log
+        let wal = std::mem::replace(&mut *WAL.lock().unwrap(), disabled_wal);
+        main::delete(kept_url).unwrap();
+        assert!(main::get_by_url(kept_url).is_none());
+        let disabled_wal = std::mem::replace(&mut *WAL.lock().unwrap(), wal);
+
+        main::load_file(&filepath).unwrap();
+        let node = main::get_by_url(kept_url).unwrap();
+        assert_eq!((node.title.as_str(), node.content.as_str(), node.mime_type.as_str()), ("kept", "kept content", "text/html"));
+        assert!(main::get_by_url(deleted_url).is_none());
+        // the replay was checkpointed, the snapshot has the page and the log is empty
+        let saved = fs::read_to_string(&filepath).unwrap();
+        assert!(saved.lines().any(|line| line.starts_with(kept_url)));
+        assert!(!saved.contains(deleted_url));
+        assert_eq!(fs::metadata(&wal_filepath).unwrap().len(), 0);
+        assert!(fs::metadata(format!("{filepath}.temp")).is_err());
+
+        // replayed pages get their field lengths from the inverted index load, until then
+        // the stats count them without
+        main::rebuild_stats();
+        *WAL.lock().unwrap() = disabled_wal;
+        assert!(main::delete(kept_url).unwrap().is_some());
+        fs::remove_dir_all(&dir).unwrap();
     }
 }
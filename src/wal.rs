@@ -0,0 +1,388 @@
+use lazy_static::lazy_static;
+use std::env;
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+// each record is u32 payload length, u32 crc32 of the payload, then the payload: a u8 kind
+// and strings as a u32 byte length and utf-8. a crash mid append leaves a short or
+// mismatching last record, replay stops there and cuts it off
+const INSERT: u8 = 0;
+const DELETE: u8 = 1;
+const HEADER_LEN: usize = 8;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Record {
+    // the columns of a url index line, published is rfc3339 or empty
+    Insert {
+        url: String,
+        title: String,
+        headings: String,
+        highlighted: String,
+        content: String,
+        language: String,
+        published: String,
+        mime_type: String,
+    },
+    Delete {
+        url: String,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FsyncPolicy {
+    // before every mutation is applied, nothing acknowledged is lost
+    Always,
+    // by a background thread, a crash loses at most the interval
+    Interval(Duration),
+    // whenever the os writes the pages back
+    Never,
+}
+
+// the open log, also the lock every url index mutation and checkpoint takes first so a
+// checkpoint never truncates a record the snapshot is missing
+pub struct Wal {
+    file: Option<File>,
+    filepath: String,
+    policy: FsyncPolicy,
+    // appended since the last fsync
+    is_dirty: bool,
+    // a failed append that couldn't be cut off, mutations are rejected until the
+    // next checkpoint empties the log
+    is_poisoned: bool,
+}
+
+impl Wal {
+    // an empty filepath is a disabled log
+    pub fn new(filepath: String, policy: FsyncPolicy) -> Wal {
+        Wal {
+            file: Option::None,
+            filepath,
+            policy,
+            is_dirty: false,
+            is_poisoned: false,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.filepath.is_empty()
+    }
+
+    fn get_file(&mut self) -> io::Result<&mut File> {
+        if self.file.is_none() {
+            let file = OpenOptions::new().create(true).append(true).open(&self.filepath)?;
+            self.file = Some(file);
+        }
+        Ok(self.file.as_mut().unwrap())
+    }
+
+    pub fn append(&mut self, record: &Record) -> io::Result<()> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+        if self.is_poisoned {
+            return Err(io::Error::other("wal is poisoned by a failed append"));
+        }
+        let bytes = main::encode(record);
+        let policy = self.policy;
+        let file = self.get_file()?;
+        let len = file.metadata()?.len();
+        let result = file.write_all(&bytes).and_then(|_| match policy {
+            FsyncPolicy::Always => file.sync_data(),
+            _ => Ok(()),
+        });
+        if let Err(err) = result {
+            // part of the record may be in the file, replay would stop there and drop every
+            // record appended after it, so it is cut off or nothing is appended anymore
+            if file.set_len(len).and_then(|_| file.sync_all()).is_err() {
+                self.is_poisoned = true;
+            }
+            return Err(err);
+        }
+        if policy != FsyncPolicy::Always {
+            self.is_dirty = true;
+        }
+        Ok(())
+    }
+
+    pub fn sync(&mut self) -> io::Result<()> {
+        if !self.is_dirty {
+            return Ok(());
+        }
+        if let Some(file) = self.file.as_mut() {
+            file.sync_data()?;
+        }
+        self.is_dirty = false;
+        Ok(())
+    }
+
+    // after a checkpoint, everything logged so far is in the snapshot
+    pub fn truncate(&mut self) -> io::Result<()> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+        let file = self.get_file()?;
+        file.set_len(0)?;
+        file.sync_all()?;
+        self.is_dirty = false;
+        self.is_poisoned = false;
+        Ok(())
+    }
+}
+
+lazy_static! {
+    pub static ref WAL: Mutex<Wal> = Mutex::new(Wal::new(main::get_filepath(), main::get_fsync_policy()));
+}
+
+pub mod main {
+    use super::*;
+
+    // next to the url index unless set, empty without a url index file and then disabled
+    pub fn get_filepath() -> String {
+        match env::var("WAL_FILE_PATH") {
+            Ok(filepath) => filepath,
+            Err(_) => env::var("URL_INDEX_FILE_PATH")
+                .map(|filepath| format!("{filepath}.wal"))
+                .unwrap_or_default(),
+        }
+    }
+
+    pub fn get_fsync_policy() -> FsyncPolicy {
+        let interval = env::var("WAL_FSYNC_INTERVAL_MS")
+            .unwrap_or(String::from("1000"))
+            .parse::<u64>()
+            .unwrap();
+        match env::var("WAL_FSYNC").unwrap_or(String::from("always")).as_str() {
+            "interval" => FsyncPolicy::Interval(Duration::from_millis(interval)),
+            "never" => FsyncPolicy::Never,
+            _ => FsyncPolicy::Always,
+        }
+    }
+
+    pub fn get_crc32(bytes: &[u8]) -> u32 {
+        let mut crc = !0u32;
+        for byte in bytes {
+            crc ^= *byte as u32;
+            for _ in 0..8 {
+                crc = match crc & 1 {
+                    1 => (crc >> 1) ^ 0xedb8_8320,
+                    _ => crc >> 1,
+                };
+            }
+        }
+        !crc
+    }
+
+    fn write_string(out: &mut Vec<u8>, value: &str) {
+        out.extend_from_slice(&(value.len() as u32).to_le_bytes());
+        out.extend_from_slice(value.as_bytes());
+    }
+
+    fn read_string(bytes: &[u8], pos: &mut usize) -> Option<String> {
+        let len = u32::from_le_bytes(bytes.get(*pos..*pos + 4)?.try_into().ok()?) as usize;
+        let value = bytes.get(*pos + 4..(*pos + 4).checked_add(len)?)?;
+        *pos += 4 + len;
+        String::from_utf8(value.to_vec()).ok()
+    }
+
+    pub fn encode(record: &Record) -> Vec<u8> {
+        let mut payload = Vec::new();
+        match record {
+            Record::Insert {
+                url,
+                title,
+                headings,
+                highlighted,
+                content,
+                language,
+                published,
+                mime_type,
+            } => {
+                payload.push(INSERT);
+                for value in [url, title, headings, highlighted, content, language, published, mime_type] {
+                    write_string(&mut payload, value);
+                }
+            }
+            Record::Delete { url } => {
+                payload.push(DELETE);
+                write_string(&mut payload, url);
+            }
+        }
+        let mut bytes = Vec::with_capacity(HEADER_LEN + payload.len());
+        bytes.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&get_crc32(&payload).to_le_bytes());
+        bytes.extend_from_slice(&payload);
+        bytes
+    }
+
+    fn decode_payload(payload: &[u8]) -> Option<Record> {
+        let mut pos = 1;
+        match *payload.first()? {
+            INSERT => {
+                let mut values = Vec::with_capacity(8);
+                for _ in 0..8 {
+                    values.push(read_string(payload, &mut pos)?);
+                }
+                let [url, title, headings, highlighted, content, language, published, mime_type]: [String; 8] =
+                    values.try_into().ok()?;
+                Some(Record::Insert {
+                    url,
+                    title,
+                    headings,
+                    highlighted,
+                    content,
+                    language,
+                    published,
+                    mime_type,
+                })
+            }
+            DELETE => Some(Record::Delete {
+                url: read_string(payload, &mut pos)?,
+            }),
+            _ => Option::None,
+        }
+    }
+
+    // the complete records in order and the length they take, bytes after it are a torn
+    // or corrupt tail
+    pub fn decode(bytes: &[u8]) -> (Vec<Record>, usize) {
+        let mut records = Vec::new();
+        let mut pos = 0;
+        while let Some(header) = bytes.get(pos..pos + HEADER_LEN) {
+            let len = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+            let crc = u32::from_le_bytes(header[4..].try_into().unwrap());
+            let Some(payload) = bytes.get(pos + HEADER_LEN..pos + HEADER_LEN + len) else {
+                break;
+            };
+            if get_crc32(payload) != crc {
+                break;
+            }
+            let Some(record) = decode_payload(payload) else {
+                break;
+            };
+            records.push(record);
+            pos += HEADER_LEN + len;
+        }
+        (records, pos)
+    }
+
+    // applies every logged mutation in order, returns how many there were
+    pub fn replay(wal: &Wal, mut apply: impl FnMut(Record)) -> Result<usize, Box<dyn Error>> {
+        if !wal.is_enabled() {
+            return Ok(0);
+        }
+        let bytes = match fs::read(&wal.filepath) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(err) => return Err(err.into()),
+        };
+        let (records, len) = decode(&bytes);
+        if len < bytes.len() {
+            println!("wal has a torn tail, dropping {} bytes after {len}", bytes.len() - len);
+            let file = OpenOptions::new().write(true).open(&wal.filepath)?;
+            file.set_len(len as u64)?;
+            file.sync_all()?;
+        }
+        let count = records.len();
+        records.into_iter().for_each(&mut apply);
+        Ok(count)
+    }
+
+    // makes a rename into the file's directory durable
+    pub fn sync_parent_dir(filepath: &str) -> io::Result<()> {
+        let dir = match Path::new(filepath).parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        File::open(dir)?.sync_all()
+    }
+
+    // the interval policy's fsyncs, the other policies need no thread
+    pub fn start_sync() {
+        let FsyncPolicy::Interval(interval) = WAL.lock().unwrap().policy else {
+            return;
+        };
+        thread::spawn(move || {
+            loop {
+                thread::sleep(interval);
+                if let Err(err) = WAL.lock().unwrap().sync() {
+                    println!("err while syncing wal : {:?}", err);
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wal_round_trips_and_stops_at_a_torn_tail() {
+        assert_eq!(main::get_crc32(b"123456789"), 0xcbf4_3926);
+        let insert = Record::Insert {
+            url: "https://a.example/ünïcode".to_string(),
+            title: "title".to_string(),
+            headings: String::new(),
+            highlighted: "b".to_string(),
+            content: "some content".to_string(),
+            language: "en".to_string(),
+            published: "2024-02-29T00:00:00+00:00".to_string(),
+            mime_type: "text/html".to_string(),
+        };
+        let delete = Record::Delete {
+            url: "https://b.example/".to_string(),
+        };
+        let mut bytes = main::encode(&insert);
+        let first_len = bytes.len();
+        bytes.extend(main::encode(&delete));
+        assert_eq!(main::decode(&bytes), (vec![insert.clone(), delete], bytes.len()));
+
+        // a crash mid append
+        assert_eq!(main::decode(&bytes[..bytes.len() - 3]), (vec![insert.clone()], first_len));
+        // a flipped bit in the last record
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        assert_eq!(main::decode(&bytes), (vec![insert], first_len));
+        assert_eq!(main::decode(&[]), (vec![], 0));
+    }
+
+    #[test]
+    fn replay_cuts_a_torn_tail_off_the_file_and_truncate_empties_it() {
+        let dir = env::temp_dir().join(format!("search-engine-wal-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let filepath = dir.join("index.txt.wal").to_string_lossy().to_string();
+        let delete = |url: &str| Record::Delete { url: url.to_string() };
+
+        let mut wal = Wal::new(filepath.clone(), FsyncPolicy::Always);
+        wal.append(&delete("https://a.example/")).unwrap();
+        wal.append(&delete("https://b.example/")).unwrap();
+        let len = fs::metadata(&filepath).unwrap().len();
+        // a crash mid append of a third record
+        let third = main::encode(&delete("https://c.example/"));
+        OpenOptions::new().append(true).open(&filepath).unwrap().write_all(&third[..third.len() - 2]).unwrap();
+
+        let mut records = Vec::new();
+        assert_eq!(main::replay(&wal, |record| records.push(record)).unwrap(), 2);
+        assert_eq!(records, vec![delete("https://a.example/"), delete("https://b.example/")]);
+        assert_eq!(fs::metadata(&filepath).unwrap().len(), len);
+
+        // records appended after the cut are replayed too
+        wal.append(&delete("https://d.example/")).unwrap();
+        assert_eq!(main::replay(&wal, |_| ()).unwrap(), 3);
+
+        wal.truncate().unwrap();
+        assert_eq!(fs::metadata(&filepath).unwrap().len(), 0);
+        assert_eq!(main::replay(&wal, |_| ()).unwrap(), 0);
+        wal.append(&delete("https://e.example/")).unwrap();
+        let mut records = Vec::new();
+        main::replay(&wal, |record| records.push(record)).unwrap();
+        assert_eq!(records, vec![delete("https://e.example/")]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}